@@ -0,0 +1,208 @@
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::Local;
+use log::{debug, info};
+use nix::errno::Errno;
+use nix::unistd::{Gid, Uid};
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+use std::{thread::sleep, time::Duration};
+
+use super::dir::{self, Dir};
+
+/// The name of the directory backups are kept in, created next to the file being backed up
+const BACKUP_DIR: &str = "keysync-backups";
+
+/// A timestamped snapshot of a file
+#[derive(Debug, PartialEq)]
+pub struct Backup {
+    pub timestamp: String,
+    pub path: PathBuf,
+}
+
+impl Backup {
+    /// Reads the contents of the backup, refusing to follow a symbolic link
+    pub fn read(&self) -> Result<String> {
+        let mut content = String::new();
+        open_nofollow(&self.path)
+            .and_then(|mut x| x.read_to_string(&mut content))
+            .with_context(|| {
+                format!("Error reading backup: {}", self.path.display())
+            })?;
+        Ok(content)
+    }
+}
+
+/// The backups of a single file, keeping only the newest copies
+#[derive(Debug)]
+pub struct Backups {
+    file: PathBuf,
+    dir: PathBuf,
+    keep: usize,
+}
+
+impl Backups {
+    /// Sets up the backups for a file, keeping at most `keep` copies
+    pub fn new<P: AsRef<Path>>(file: P, keep: usize) -> Self {
+        let file = file.as_ref().to_path_buf();
+        let dir = match file.parent() {
+            Some(p) => p.join(BACKUP_DIR),
+            None => PathBuf::from(BACKUP_DIR),
+        };
+        Backups { file, dir, keep }
+    }
+
+    /// Snapshots the current file in the directory, owned by the same user with the same mode, then removes the oldest copies
+    /// Returns None if backups are disabled or the file does not exist yet
+    /// Everything is done relative to the open directory, since this runs as root in directories the user controls
+    pub fn create(&self, dir: &Dir) -> Result<Option<Backup>> {
+        if self.keep == 0 {
+            return Ok(None);
+        }
+        let name = self.name();
+        let source = match dir.open_file(&name).with_context(|| {
+            format!("Failed to open [{}] for backup", self.file.display())
+        })? {
+            Some(f) => f,
+            None => return Ok(None),
+        };
+        let stat = source.stat()?;
+        if !dir::is_file(&stat) {
+            return Ok(None);
+        }
+        let content = source.read_to_end().with_context(|| {
+            format!("Failed to read [{}] for backup", self.file.display())
+        })?;
+        let owner = (Uid::from_raw(stat.st_uid), Gid::from_raw(stat.st_gid));
+        let mode = stat.st_mode & 0o777;
+        let mut backups = dir
+            .subdir(BACKUP_DIR, Some((owner, 0o700)))?
+            .with_context(|| {
+                format!(
+                    "Failed to create the backup directory [{}]",
+                    self.dir.display()
+                )
+            })?;
+
+        // Timestamps need to be unique, so wait out any backup made in the same millisecond
+        let (timestamp, file_name, file) = loop {
+            let timestamp =
+                Local::now().format("%Y%m%d-%H%M%S%.3f").to_string();
+            let file_name = format!("{}.{}", name, timestamp);
+            // Created exclusively, so an existing file or symbolic link is never written through
+            match backups.create_file(&file_name, mode) {
+                Ok(file) => break (timestamp, file_name, file),
+                Err(Errno::EEXIST) => sleep(Duration::from_millis(1)),
+                Err(e) => {
+                    return Err(Error::new(e).context(format!(
+                        "Failed to backup [{}]",
+                        self.file.display()
+                    )))
+                }
+            }
+        };
+        let path = self.dir.join(&file_name);
+        file.write_all(&content)
+            .and_then(|_| file.set_owner(owner.0, owner.1, mode))
+            .and_then(|_| file.sync())
+            .with_context(|| {
+                format!(
+                    "Failed to backup [{}] to [{}]",
+                    self.file.display(),
+                    path.display()
+                )
+            })?;
+        info!("Backed up {} to {}", self.file.display(), path.display());
+
+        self.prune(&mut backups)?;
+        Ok(Some(Backup { timestamp, path }))
+    }
+
+    /// Gets the list of backups, oldest first, only regular files are included
+    /// A symbolic link in place of the backup directory is refused rather than followed
+    pub fn list(&self) -> Result<Vec<Backup>> {
+        match Dir::open(&self.dir, None)? {
+            Some(mut d) => self.list_in(&mut d),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Gets the list of backups in the open backup directory, oldest first
+    fn list_in(&self, dir: &mut Dir) -> Result<Vec<Backup>> {
+        let prefix = format!("{}.", self.name());
+        let mut backups: Vec<Backup> = dir
+            .files()?
+            .into_iter()
+            .filter_map(|name| {
+                name.strip_prefix(&prefix).filter(|t| is_timestamp(t)).map(
+                    |t| Backup {
+                        timestamp: t.to_owned(),
+                        path: self.dir.join(&name),
+                    },
+                )
+            })
+            .collect();
+        backups.sort_by(|a, b| a.timestamp.cmp(&b.timestamp)); // Timestamps are fixed width, so they sort by time
+        Ok(backups)
+    }
+
+    /// Finds the backup with the given timestamp, or the newest if none is given
+    pub fn find(&self, timestamp: Option<&str>) -> Result<Backup> {
+        let mut backups = self.list()?;
+        match timestamp {
+            Some(t) => backups
+                .into_iter()
+                .find(|x| x.timestamp == t)
+                .ok_or_else(|| anyhow!("No backup with timestamp: {}", t)),
+            None => backups.pop().ok_or_else(|| {
+                anyhow!("No backups of {} exist", self.file.display())
+            }),
+        }
+    }
+
+    /// Removes the oldest backups in the open backup directory until only the amount to keep is left
+    fn prune(&self, dir: &mut Dir) -> Result<()> {
+        let backups = self.list_in(dir)?;
+        let excess = backups.len().saturating_sub(self.keep);
+        for backup in backups.iter().take(excess) {
+            debug!("Removing old backup {}", backup.path.display());
+            let name = format!("{}.{}", self.name(), backup.timestamp);
+            dir.remove(&name).with_context(|| {
+                format!(
+                    "Failed to remove old backup [{}]",
+                    backup.path.display()
+                )
+            })?;
+        }
+        Ok(())
+    }
+
+    /// The file name of the file being backed up
+    fn name(&self) -> String {
+        self.file
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .unwrap_or_default()
+    }
+}
+
+/// Opens a file for reading, failing if it is a symbolic link
+fn open_nofollow(path: &Path) -> io::Result<File> {
+    fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(nix::libc::O_NOFOLLOW)
+        .open(path)
+}
+
+/// Returns true if the string is formatted like a backup timestamp, Ex: 20220601-142501.123
+fn is_timestamp(s: &str) -> bool {
+    s.len() == 19
+        && s.chars()
+            .all(|c| c.is_ascii_digit() || c == '-' || c == '.')
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/backup.rs"]
+mod test;
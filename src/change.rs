@@ -0,0 +1,148 @@
+use similar::TextDiff;
+use std::fmt;
+use std::path::PathBuf;
+
+use super::keys::{self, AuthorizedKey};
+use super::provenance::{is_managed, Provenance, Source};
+
+/// A change to an authorized keys file, made or only planned on a dry run
+#[derive(Debug)]
+pub struct Change {
+    pub path: PathBuf,
+    /// The contents of the file before the change
+    pub before: String,
+    /// The contents of the file after the change
+    pub after: String,
+    pub added: Vec<AuthorizedKey>,
+    pub removed: Vec<AuthorizedKey>,
+    /// Imported keys that were already in the file with different options, and were not added
+    pub conflicts: Vec<Conflict>,
+    /// Lines tidy removed, with the reason for each
+    pub removals: Vec<Removal>,
+}
+
+/// Why tidy removes a line
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Reason {
+    /// A later copy of a key with the same options, sshd only uses the first
+    Duplicate,
+    /// A key keysync imported that has expired
+    Expired,
+    /// A line that is neither a key nor a comment
+    Malformed,
+}
+
+/// Implements display for Reason
+impl fmt::Display for Reason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Reason::Duplicate => write!(f, "duplicate"),
+            Reason::Expired => write!(f, "expired"),
+            Reason::Malformed => write!(f, "malformed"),
+        }
+    }
+}
+
+/// A line tidy removes, with the key on it if it could be parsed
+#[derive(Debug, PartialEq)]
+pub struct Removal {
+    pub reason: Reason,
+    /// The line number in the file before the change, starting at 1
+    pub line: usize,
+    pub text: String,
+    pub key: Option<AuthorizedKey>,
+}
+
+/// Implements display for Removal
+impl fmt::Display for Removal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(
+                f,
+                "{} {} {} {} {}",
+                self.reason,
+                key.bits,
+                key.fingerprint,
+                key.key_type,
+                source(key)
+            ),
+            None => {
+                write!(f, "{} line {}: {}", self.reason, self.line, self.text)
+            }
+        }
+    }
+}
+
+/// An imported key that is already in the file with different options, sshd only uses the first entry
+#[derive(Debug, PartialEq)]
+pub struct Conflict {
+    pub existing: AuthorizedKey,
+    pub imported: AuthorizedKey,
+}
+
+/// Implements display for Conflict
+impl fmt::Display for Conflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Conflict {} {}: existing options '{}' differ from imported options '{}'",
+            self.existing.fingerprint,
+            self.existing.key_type,
+            keys::format_options(&self.existing.options),
+            keys::format_options(&self.imported.options)
+        )
+    }
+}
+
+impl Change {
+    /// Returns true if the file is left as is
+    pub fn is_empty(&self) -> bool {
+        self.before == self.after
+    }
+
+    /// Formats a unified diff of the file, followed by the fingerprint and source of each added and removed key, the lines tidy removed and why, and any conflicts
+    pub fn diff(&self) -> String {
+        let path = self.path.display().to_string();
+        let mut conflicts = String::new();
+        for conflict in self.conflicts.iter() {
+            conflicts.push_str(&format!("! {}\n", conflict));
+        }
+        if self.is_empty() {
+            return conflicts;
+        }
+        let mut diff = TextDiff::from_lines(&self.before, &self.after)
+            .unified_diff()
+            .header(&path, &path)
+            .to_string();
+        for (sign, keys) in [('+', &self.added), ('-', &self.removed)] {
+            for key in keys.iter() {
+                diff.push_str(&format!(
+                    "{} {} {} {} {}\n",
+                    sign,
+                    key.bits,
+                    key.fingerprint,
+                    key.key_type,
+                    source(key)
+                ));
+            }
+        }
+        for removal in self.removals.iter() {
+            diff.push_str(&format!("- {}\n", removal));
+        }
+        diff + &conflicts
+    }
+}
+
+/// Where a key came from Ex: github:bob, or local if it was not imported by keysync
+fn source(key: &AuthorizedKey) -> String {
+    match key.comment.as_deref().and_then(Provenance::from_comment) {
+        Some(p) => Source::from(&p).to_string(),
+        None if is_managed(key) => String::from("keysync"),
+        None => String::from("local"),
+    }
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/change.rs"]
+mod test;
@@ -1,11 +1,19 @@
 use clap::{
-    crate_authors, crate_description, crate_name, crate_version, Arg, Command,
+    crate_authors, crate_description, crate_name, crate_version, Arg, ArgGroup,
+    Command,
 };
 use cron::Schedule;
-use nix::unistd::User;
+use nix::unistd::{Group, User};
+use std::path::Path;
 use std::str::FromStr;
 use url::Url;
 
+use super::keys;
+use super::mapping;
+use super::policy::Policy;
+use super::provenance::Source;
+use super::util;
+
 /// Struct of default key downloading schedules
 #[derive(Debug)]
 pub enum DefaultCron {
@@ -57,7 +65,7 @@ pub fn app() -> Command<'static> {
         .long("dry-run");
 
     let arg_username = Arg::new("username")
-        .help("The username of the account")
+        .help("The username of the account, or the URL to retrieve from with --url")
         .required(true)
         .index(1);
 
@@ -73,11 +81,98 @@ pub fn app() -> Command<'static> {
         .forbid_empty_values(true)
         .validator(is_url_or_empty);
 
+    let arg_url = Arg::new("url")
+        .help("Retrieve from the URL given as the username, which serves keys one per line")
+        .long("url")
+        .conflicts_with_all(&["github", "gitlab", "launchpad"]);
+
     let arg_launchpad = Arg::new("launchpad")
         .help("Retrieve from Launchpad")
         .short('l')
         .long("launchpad");
 
+    let arg_user = Arg::new("user")
+        .help("The local user account")
+        .required(false)
+        .value_name("USER")
+        .validator(is_user)
+        .long("user")
+        .short('u');
+
+    let arg_users = arg_user
+        .clone()
+        .help("The local user accounts, repeated or comma separated Ex: deploy,ci,backup")
+        .multiple_occurrences(true)
+        .use_value_delimiter(true);
+
+    let arg_group = Arg::new("group")
+        .help("Every member of a local group, listed in the group database or with it as their primary group")
+        .value_name("GROUP")
+        .long("group")
+        .validator(is_group)
+        .conflicts_with("user");
+
+    let arg_options = Arg::new("options")
+        .help("sshd options to prepend to imported keys Ex: 'restrict,from=\"10.0.0.0/8\"'")
+        .value_name("OPTIONS")
+        .short('o')
+        .long("options")
+        .validator(is_key_options)
+        .conflicts_with("trusted_ca");
+
+    let arg_cert_authority = Arg::new("cert_authority")
+        .help("Import the keys as certificate authorities, trusted to sign certificates for the user")
+        .long("cert-authority")
+        .conflicts_with("trusted_ca");
+
+    let arg_principals = Arg::new("principals")
+        .help("Comma separated principals that certificates must contain, instead of the username Ex: 'alice,deploy'")
+        .value_name("PRINCIPALS")
+        .long("principals")
+        .requires("cert_authority");
+
+    let arg_ttl = Arg::new("ttl")
+        .help("Imported keys expire after a duration Ex: 30m, 8h, 2d, 1w")
+        .value_name("DURATION")
+        .long("ttl")
+        .validator(is_duration)
+        .conflicts_with_all(&["expires", "trusted_ca"]);
+
+    let arg_expires = Arg::new("expires")
+        .help("Imported keys expire at a local date and optional time Ex: 2022-06-30, 2022-06-30T18:00")
+        .value_name("DATE")
+        .long("expires")
+        .validator(is_date)
+        .conflicts_with("trusted_ca");
+
+    let arg_central = Arg::new("central")
+        .help("Use a root owned file in a central directory instead of the users authorized_keys, with optional directory")
+        .value_name("DIR")
+        .long("central")
+        .min_values(0)
+        .require_equals(true)
+        .validator(is_absolute_path);
+
+    let arg_trusted_ca = Arg::new("trusted_ca")
+        .help("Use the TrustedUserCAKeys file of sshd to trust the keys as CAs for every user, with optional file")
+        .value_name("FILE")
+        .long("trusted-ca")
+        .min_values(0)
+        .require_equals(true)
+        .validator(is_absolute_path)
+        .conflicts_with("central");
+
+    let arg_cache = Arg::new("cache")
+        .help("Use a root owned file in the cache directory, which sshd reads through authorized-keys-command")
+        .long("cache")
+        .conflicts_with_all(&["central", "trusted_ca"]);
+
+    let arg_policy = Arg::new("policy")
+        .help("Requirements imported keys have to meet Ex: 'allow=ssh-ed25519,sk-* deny=ssh-dss min-rsa-bits=3072'")
+        .value_name("POLICY")
+        .long("policy")
+        .validator(is_policy);
+
     // Now define the subcommands
     let get = Command::new("get")
         .about("Retrieves a key from an online source")
@@ -85,35 +180,31 @@ pub fn app() -> Command<'static> {
         .arg(&arg_github)
         .arg(&arg_launchpad)
         .arg(&arg_gitlab)
+        .arg(&arg_url)
+        .arg(&arg_options)
+        .arg(&arg_cert_authority)
+        .arg(&arg_principals)
+        .arg(&arg_ttl)
+        .arg(&arg_expires)
+        .arg(&arg_policy)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
         .arg(&arg_dry_run)
-        .arg(
-            Arg::new("user")
-                .help("The local user account")
-                .required(false)
-                .value_name("USER")
-                .validator(is_user)
-                .long("user")
-                .short('u'),
-        );
+        .arg(&arg_users)
+        .arg(&arg_group);
 
     let set = Command::new("set")
         .about("Add an automatic job")
-        .arg(
-            Arg::new("user")
-                .help("The local user account")
-                .required(false)
-                .value_name("USER")
-                .validator(is_user)
-                .long("user")
-                .short('u'),
-        )
+        .arg(&arg_users)
+        .arg(&arg_group)
         .arg(&arg_username)
         .arg(
             Arg::new("schedule")
                 .help("Default schedules")
                 .required(true)
                 .index(2)
-                .possible_values(&["Hourly", "Daily", "Weekly", "Monthly"])
+                .possible_values(["Hourly", "Daily", "Weekly", "Monthly"])
                 .ignore_case(true)
                 .conflicts_with("cron"),
         )
@@ -130,6 +221,15 @@ pub fn app() -> Command<'static> {
         .arg(&arg_github)
         .arg(&arg_launchpad)
         .arg(&arg_gitlab)
+        .arg(&arg_url)
+        .arg(&arg_options)
+        .arg(&arg_cert_authority)
+        .arg(&arg_principals)
+        .arg(&arg_expires)
+        .arg(&arg_policy)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
         .arg(&arg_skip_check)
         .arg(&arg_dry_run);
 
@@ -148,6 +248,94 @@ pub fn app() -> Command<'static> {
         .about("List enabled job(s)")
         .arg(&arg_skip_check);
 
+    let keys = Command::new("keys")
+        .about("List the keys in an authorized_keys file, and which keysync manages")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(Arg::new("json").help("Output as JSON").long("json"));
+
+    let remove_key = Command::new("remove-key")
+        .about("Remove keys from an authorized_keys file by fingerprint, index or source")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(
+            Arg::new("keys")
+                .help("SHA256 fingerprints or indexes (as listed by keys) of the keys to remove")
+                .value_name("KEY")
+                .multiple_occurrences(true),
+        )
+        .arg(
+            Arg::new("source")
+                .help("Remove every key imported from a source Ex: gh:bob, gl:bob, lp:bob, gitlab")
+                .value_name("SOURCE")
+                .long("source")
+                .validator(is_source),
+        )
+        .group(ArgGroup::new("selector").args(&["keys", "source"]).required(true).multiple(true))
+        .arg(&arg_dry_run);
+
+    let backups = Command::new("backups")
+        .about("List the backups of an authorized_keys file")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache);
+
+    let rollback = Command::new("rollback")
+        .about("Restore an authorized_keys file from a backup")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(
+            Arg::new("to")
+                .help("The timestamp of the backup to restore, defaults to the newest")
+                .value_name("TIMESTAMP")
+                .long("to"),
+        )
+        .arg(&arg_dry_run);
+
+    let doctor = Command::new("doctor")
+        .about("Report permission and ownership problems that make sshd refuse the authorized_keys file")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(Arg::new("fix").help("Repair the owner and modes of the file and its directory").long("fix"));
+
+    let tidy = Command::new("tidy")
+        .about("Rewrite an authorized_keys file without duplicate keys, expired imported keys and stray whitespace")
+        .arg(&arg_user)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(&arg_dry_run);
+
+    let apply = Command::new("apply")
+        .about("Sync the keys of the remote identities in the mapping file to every local user, removing the keys it imported that are no longer mapped")
+        .arg(
+            Arg::new("file")
+                .help("The mapping file, each local user or %group to a list of identities Ex: deploy = [\"gh:alice\", \"lp:asmith\"]")
+                .value_name("FILE")
+                .long("file")
+                .short('f')
+                .default_value(mapping::MAPPING_PATH),
+        )
+        .arg(&arg_policy)
+        .arg(&arg_central)
+        .arg(&arg_trusted_ca)
+        .arg(&arg_cache)
+        .arg(&arg_dry_run);
+
+    let authorized_keys_command = Command::new("authorized-keys-command")
+        .about("Print the cached keys of a user, for the AuthorizedKeysCommand of sshd Ex: keysync authorized-keys-command %u %f")
+        .arg(Arg::new("account").help("The local user sshd is authenticating").value_name("USER").required(true).index(1))
+        .arg(Arg::new("fingerprint").help("Only print the key with this SHA256 fingerprint").value_name("FINGERPRINT").index(2));
+
     let daemon = Command::new("daemon")
         .about("Runs job daemon in background (No need to run, systemd will manage for you)")
         .arg(Arg::new("install").help("Install the Systemd service file").long("install"))
@@ -162,6 +350,14 @@ pub fn app() -> Command<'static> {
         .subcommand(set)
         .subcommand(remove)
         .subcommand(jobs)
+        .subcommand(keys)
+        .subcommand(remove_key)
+        .subcommand(tidy)
+        .subcommand(backups)
+        .subcommand(rollback)
+        .subcommand(doctor)
+        .subcommand(apply)
+        .subcommand(authorized_keys_command)
         .subcommand(daemon)
         .arg(
             Arg::new("verbosity")
@@ -193,6 +389,54 @@ fn is_cron(val: &str) -> Result<(), String> {
         .map_err(|x| x.to_string())
 }
 
+/// Custom validator, returns () if val is a valid sshd option list, error otherwise
+fn is_key_options(val: &str) -> Result<(), String> {
+    keys::parse_options(val)
+        .and_then(|x| keys::validate_options(&x))
+        .map_err(|x| x.to_string())
+}
+
+/// Custom validator, returns () if val is a valid key policy spec, error otherwise
+fn is_policy(val: &str) -> Result<(), String> {
+    val.parse::<Policy>().map(|_| ()).map_err(|x| x.to_string())
+}
+
+/// Custom validator, returns () if val is a valid key source, error otherwise
+fn is_source(val: &str) -> Result<(), String> {
+    val.parse::<Source>().map(|_| ()).map_err(|x| x.to_string())
+}
+
+/// Custom validator, returns () if val is a duration, error otherwise
+fn is_duration(val: &str) -> Result<(), String> {
+    util::parse_duration(val)
+        .map(|_| ())
+        .map_err(|x| x.to_string())
+}
+
+/// Custom validator, returns () if val is a date, error otherwise
+fn is_date(val: &str) -> Result<(), String> {
+    util::parse_date(val).map(|_| ()).map_err(|x| x.to_string())
+}
+
+/// Custom validator, returns () if val is an absolute path, error otherwise
+fn is_absolute_path(val: &str) -> Result<(), String> {
+    if Path::new(val).is_absolute() {
+        Ok(())
+    } else {
+        Err(format!("'{}' is not an absolute path", val))
+    }
+}
+
+/// Custom validator, returns () if val the group exists on the system, error otherwise
+fn is_group(val: &str) -> Result<(), String> {
+    let result = Group::from_name(val).map_err(|x| x.to_string())?;
+    if result.is_none() {
+        Err(format!("group '{}' does not exist on system", val))
+    } else {
+        Ok(())
+    }
+}
+
 /// Custom validator, returns () if val the user exists on the system, error otherwise
 fn is_user(val: &str) -> Result<(), String> {
     let result = User::from_name(val).map_err(|x| x.to_string())?;
@@ -0,0 +1,104 @@
+use anyhow::{Context, Result};
+use chrono::Duration;
+use serde::Deserialize;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::policy::Policy;
+use super::util;
+
+/// The default path of the keysync configuration file
+const CONFIG_PATH: &str = "/etc/keysync/config.toml";
+
+/// What to do when an imported key is already in the file with different options
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum OnConflict {
+    /// Keep the existing entry and report the conflict
+    Report,
+    /// Do not write any of the keys
+    Refuse,
+}
+
+/// Global settings, read from the configuration file
+/// Any setting missing from the file uses its default
+#[derive(Debug, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+    /// Amount of authorized_keys backups to keep per file
+    pub backups: usize,
+    /// The sshd config to read AuthorizedKeysFile from
+    pub sshd_config: PathBuf,
+    /// The directory keys are written to for jobs using a central key directory
+    pub central_dir: PathBuf,
+    /// The directory keys are cached in for jobs serving keys through AuthorizedKeysCommand
+    pub cache_dir: PathBuf,
+    /// Repair the owner and modes of authorized_keys files after writing, instead of only warning
+    pub fix_permissions: bool,
+    /// What to do when an imported key is already in the file with different options
+    pub on_conflict: OnConflict,
+    /// Remove the keys a job imported once its remote account was missing this many runs in a row, 0 never removes them
+    pub remove_missing_after: u32,
+    /// How long jobs hold new keys back before writing them Ex: 24h, empty writes them right away
+    pub quarantine: String,
+    /// Self-hosted GitLab instances, whose API is used for expiry dates and account pinning like gitlab.com
+    pub gitlab_hosts: Vec<String>,
+    /// Requirements every imported key has to meet, in addition to the policy of the job
+    pub policy: Policy,
+}
+
+/// Implements the default settings
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            backups: 10,
+            sshd_config: PathBuf::from("/etc/ssh/sshd_config"),
+            central_dir: PathBuf::from("/etc/keysync/keys"),
+            cache_dir: PathBuf::from("/var/cache/keysync"),
+            fix_permissions: false,
+            on_conflict: OnConflict::Report,
+            remove_missing_after: 0,
+            quarantine: String::new(),
+            gitlab_hosts: vec![],
+            policy: Policy::default(),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration from the default path, if the file does not exist the defaults are used
+    pub fn load() -> Result<Self> {
+        Config::load_path(CONFIG_PATH)
+    }
+
+    /// Loads the configuration from a given path, if the file does not exist the defaults are used
+    fn load_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        if !path.is_file() {
+            return Ok(Config::default());
+        }
+        let text = fs::read_to_string(path).with_context(|| {
+            format!("Error reading config file: {}", path.display())
+        })?;
+        let config: Config = toml::from_str(&text).with_context(|| {
+            format!("Error parsing config file: {}", path.display())
+        })?;
+        config.quarantine().with_context(|| {
+            format!("Error parsing config file: {}", path.display())
+        })?;
+        Ok(config)
+    }
+
+    /// Gets how long jobs hold new keys back, None if they write them right away
+    pub fn quarantine(&self) -> Result<Option<Duration>> {
+        if self.quarantine.is_empty() {
+            return Ok(None);
+        }
+        util::parse_duration(&self.quarantine).map(Some)
+    }
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/config.rs"]
+mod test;
@@ -1,13 +1,18 @@
-use anyhow::Result;
+use anyhow::{anyhow, Result};
+use chrono::Utc;
 use filetime::FileTime;
 use job_scheduler::{Job, JobScheduler};
-use log::{debug, error, info};
+use log::{debug, error, info, warn};
 use std::{str::FromStr, thread::sleep, time::Duration};
 use url::Url;
 
+use super::config::Config;
 use super::db::{db_last_modified, Database, Schedule};
-use super::file::AuthorizedKeys;
-use super::http::Network;
+use super::file::{AuthorizedKeys, Target};
+use super::http::{self, Network};
+use super::keys::{AuthorizedKey, KeyOption};
+use super::policy::{self, Policy};
+use super::provenance;
 
 /// An implementation of the daemon
 pub struct Daemon {
@@ -38,10 +43,9 @@ impl Daemon {
                 db_last_modified().unwrap_or(self.last_modified);
             if self.last_modified != modified {
                 self.last_modified = modified;
-                let scheduler: JobScheduler = JobScheduler::new();
-                // Schedule tasks
+                // Replace the scheduler before scheduling, so the jobs are added to the new one
+                self.scheduler = JobScheduler::new();
                 self.schedule();
-                self.scheduler = scheduler;
             }
             self.scheduler.tick();
             sleep(self.sleep_time);
@@ -67,8 +71,15 @@ impl Daemon {
             }
         };
 
+        let config: Config = match Config::load() {
+            Ok(c) => c,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        };
+
         for schedule in schedules {
-            let user: String = schedule.user.to_string();
             let url: Url = match Url::parse(&schedule.url) {
                 Ok(u) => u,
                 Err(e) => {
@@ -77,20 +88,50 @@ impl Daemon {
                 }
             };
 
-            match AuthorizedKeys::open(Some(&user)) {
-                Ok(_) => debug!(
-                    "authorized keys file for {} exists or was created",
-                    &user
-                ),
+            let target: Target = match schedule.key_target() {
+                Ok(t) => t,
                 Err(e) => {
-                    error!(
-                        "Unable to create authorized keys file for user {}. {}",
-                        &user, e
-                    );
+                    error!("{}", e);
                     continue;
                 }
+            };
+
+            let users: Vec<String> = match schedule.users() {
+                Ok(u) => u,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+            for user in users.iter() {
+                match AuthorizedKeys::open(Some(user), &target, &config) {
+                    Ok(_) => debug!(
+                        "authorized keys file for {} exists or was created",
+                        user
+                    ),
+                    Err(e) => error!(
+                        "Unable to create authorized keys file for user {}. {}",
+                        user, e
+                    ),
+                }
             }
 
+            let options: Vec<KeyOption> = match schedule.key_options() {
+                Ok(o) => o,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+
+            let policy: Policy = match schedule.key_policy() {
+                Ok(p) => p,
+                Err(e) => {
+                    error!("{}", e);
+                    continue;
+                }
+            };
+
             let cron = match job_scheduler::Schedule::from_str(&schedule.cron) {
                 Ok(c) => c,
                 Err(e) => {
@@ -100,17 +141,31 @@ impl Daemon {
             };
 
             let job = Job::new(cron, move || {
-                run_job(user.to_owned(), url.to_owned())
+                run_job(&schedule, url.to_owned(), &options, &target, &policy)
             });
             self.scheduler.add(job);
         }
     }
 }
 
-/// Runs a job that is on the schedule
-fn run_job(user: String, url: Url) {
-    let network = Network::new();
-    let keys = match network.get_keys(&url) {
+/// Runs a job that is on the schedule, for each of its users
+fn run_job(
+    schedule: &Schedule,
+    url: Url,
+    options: &[KeyOption],
+    target: &Target,
+    policy: &Policy,
+) {
+    // Resolved on every run, so a group job follows its members
+    let users: Vec<String> = match schedule.users() {
+        Ok(u) => u,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    let config = match Config::load() {
         Ok(c) => c,
         Err(e) => {
             error!("{}", e);
@@ -118,7 +173,201 @@ fn run_job(user: String, url: Url) {
         }
     };
 
-    let authorized_keys = match AuthorizedKeys::open(Some(&user)) {
+    let network = Network::new().with_gitlab_hosts(&config.gitlab_hosts);
+    let keys = match verify_account(&schedule.url, &network, false)
+        .and_then(|_| network.get_keys(&url, schedule.id))
+    {
+        Ok(k) => k,
+        Err(e) if http::is_gone(&e) => {
+            account_missing(schedule, &users, target, &config, e);
+            return;
+        }
+        Err(e) if http::is_rate_limited(&e) => {
+            warn!("{}, leaving the keys as they are until a later run", e);
+            return;
+        }
+        Err(e) => {
+            error!("{}, leaving the keys as they are", e);
+            return;
+        }
+    };
+    if schedule.missing_count > 0 {
+        set_missing_count(schedule, 0);
+    }
+
+    let (keys, rejected) = policy::enforce(keys, &[&config.policy, policy]);
+    for rejection in rejected.iter() {
+        warn!("{} for {}", rejection, schedule.user);
+    }
+
+    let keys = match config.quarantine() {
+        Ok(Some(period)) => match quarantine(schedule, keys, period) {
+            Ok(k) => k,
+            Err(e) => {
+                error!("{}", e);
+                return;
+            }
+        },
+        Ok(None) => keys,
+        Err(e) => {
+            error!("{}", e);
+            return;
+        }
+    };
+
+    for user in users.iter() {
+        write_job_keys(schedule, user, keys.clone(), options, target, &config);
+    }
+}
+
+/// Checks the remote account of a keys url still has the ID it was pinned to, so a renamed and re-registered username is not trusted
+/// A url that is not pinned yet is pinned to the current ID, unless on a dry run or the database cannot be written
+/// Only the first pin is put off when the provider is unavailable, a pinned url that cannot be checked is an error
+pub fn verify_account(
+    url: &str,
+    network: &Network,
+    dry_run: bool,
+) -> Result<()> {
+    let database = match Database::open() {
+        Ok(d) => d,
+        Err(e) => {
+            warn!("{}, the account of {} is not checked", e, url);
+            return Ok(());
+        }
+    };
+    let pinned = database.pinned_account(url)?;
+    if pinned.is_none() && dry_run {
+        return Ok(());
+    }
+    let account_id = match network.account_id(url) {
+        Ok(Some(i)) => i,
+        Ok(None) => return Ok(()),
+        Err(e) if pinned.is_none() => {
+            warn!("{}, pinning the account on a later run", e);
+            return Ok(());
+        }
+        Err(e) => return Err(e.context("Unable to verify the pinned account")),
+    };
+
+    match pinned {
+        None => {
+            match database.pin_account(url, &account_id) {
+                Ok(()) => {
+                    info!("Pinned {} to account ID {}", url, account_id)
+                }
+                Err(e) => warn!("{}, {} is not pinned", e, url),
+            }
+            Ok(())
+        }
+        Some(p) if p != account_id => Err(anyhow!(
+            "Refusing to import from {}, the account ID changed from {} to {}, the username may have been taken over by someone else",
+            url,
+            p,
+            account_id
+        )),
+        Some(_) => Ok(()),
+    }
+}
+
+/// Holds back the keys served to a job for less than the quarantine period, a fresh key is the mark of a taken over account
+/// The provider's creation time is used when it has one, otherwise when the job first saw the key
+fn quarantine(
+    schedule: &Schedule,
+    keys: Vec<AuthorizedKey>,
+    period: chrono::Duration,
+) -> Result<Vec<AuthorizedKey>> {
+    let job = match schedule.id {
+        Some(j) => j,
+        None => return Ok(keys),
+    };
+    let database = Database::open()?;
+    let now = Utc::now();
+    let mut trusted: Vec<AuthorizedKey> = vec![];
+    for key in keys.iter() {
+        let since = database.key_since(
+            job,
+            &key.fingerprint,
+            key.created.unwrap_or(now),
+        )?;
+        if now - since >= period {
+            trusted.push(key.clone());
+        } else {
+            info!(
+                "Holding {} {} of {} back until {}",
+                key.fingerprint,
+                key.key_type,
+                schedule.url,
+                since + period
+            );
+        }
+    }
+    let served: Vec<String> = keys.into_iter().map(|x| x.fingerprint).collect();
+    database.forget_keys(job, &served)?;
+    Ok(trusted)
+}
+
+/// Counts a run that found the remote account of a job missing, and removes the keys it imported once the configured count is reached
+fn account_missing(
+    schedule: &Schedule,
+    users: &[String],
+    target: &Target,
+    config: &Config,
+    error: anyhow::Error,
+) {
+    let count = schedule.missing_count + 1;
+    warn!("{}, {} run(s) in a row", error, count);
+    set_missing_count(schedule, count);
+    if config.remove_missing_after == 0 || count < config.remove_missing_after {
+        return;
+    }
+
+    // Only the keys this job imported, other jobs or commands may import the same account for other reasons
+    let job = match schedule.id {
+        Some(j) => j,
+        None => return,
+    };
+    for user in users.iter() {
+        let removed = AuthorizedKeys::open(Some(user), target, config)
+            .and_then(|x| {
+                x.remove_keys(|_, key| provenance::is_from_job(key, job), false)
+            });
+        match removed {
+            Ok(change) => {
+                for key in change.removed.iter() {
+                    println!(
+                        "Removed {} {} of missing account {} for {}",
+                        key.fingerprint, key.key_type, schedule.url, user
+                    );
+                }
+            }
+            Err(e) => error!("{}", e),
+        }
+    }
+}
+
+/// Stores how many runs in a row found the remote account of a job missing
+fn set_missing_count(schedule: &Schedule, count: u32) {
+    if let Some(job) = schedule.id {
+        if let Err(e) =
+            Database::open().and_then(|x| x.set_missing_count(job, count))
+        {
+            warn!("{}", e);
+        }
+    }
+}
+
+/// Writes the keys retrieved by a job for one of its users
+/// Files keysync owns hold exactly what the job was served, so keys it no longer serves are removed
+fn write_job_keys(
+    schedule: &Schedule,
+    user: &str,
+    keys: Vec<AuthorizedKey>,
+    options: &[KeyOption],
+    target: &Target,
+    config: &Config,
+) {
+    let authorized_keys = match AuthorizedKeys::open(Some(user), target, config)
+    {
         Ok(a) => a,
         Err(e) => {
             error!("{}", e);
@@ -126,9 +375,34 @@ fn run_job(user: String, url: Url) {
         }
     };
 
-    match authorized_keys.write_keys(keys, false) {
-        Ok(count) => {
-            println!("Added {} keys to a {} authorized_keys file", user, count)
+    let written = match (target, schedule.id) {
+        (Target::Cache(_) | Target::Central(_), Some(job)) => {
+            authorized_keys.replace_job_keys(job, keys, options, false)
+        }
+        _ => authorized_keys.write_keys(keys, options, false),
+    };
+    match written {
+        Ok(change) => {
+            for key in change.added.iter() {
+                println!(
+                    "Added {} {} for {}",
+                    key.fingerprint, key.key_type, user
+                );
+            }
+            for key in change.removed.iter() {
+                println!(
+                    "Removed {} {} for {}, it is expired or no longer served",
+                    key.fingerprint, key.key_type, user
+                );
+            }
+            for conflict in change.conflicts.iter() {
+                warn!("{} for {}", conflict, user);
+            }
+            println!(
+                "Added {} keys to a {} authorized_keys file",
+                change.added.len(),
+                user
+            )
         }
         Err(e) => error!("{}", e),
     };
@@ -1,6 +1,7 @@
 use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
 use filetime::FileTime;
-use rusqlite::{params, Connection, Error};
+use rusqlite::{params, Connection, Error, OptionalExtension};
 use std::{
     fmt, fs,
     path::{Path, PathBuf},
@@ -8,6 +9,25 @@ use std::{
 };
 use url::Url;
 
+use super::file::Target;
+use super::keys::{self, KeyOption};
+use super::policy::Policy;
+use super::util;
+
+/// Prefix of the user of a job that imports into every member of a group Ex: %devops
+pub const GROUP_PREFIX: char = '%';
+
+/// Columns added after the table was first created, with their definitions
+const COLUMNS: &[(&str, &str)] = &[
+    ("options", "text not null default ''"),
+    ("target", "text not null default 'home'"),
+    ("policy", "text not null default ''"),
+    ("missing_count", "integer not null default 0"),
+];
+
+/// The columns of a schedule that are given when it is created
+type Columns = (u32, String, String, String, String, String, String);
+
 /// A Schedule representation
 #[derive(Debug, PartialEq)]
 pub struct Schedule {
@@ -15,6 +35,11 @@ pub struct Schedule {
     pub user: String,
     pub cron: String,
     pub url: String,
+    pub options: String,
+    pub target: String,
+    pub policy: String,
+    /// How many runs in a row found the remote account missing
+    pub missing_count: u32,
 }
 
 /// Implements display for Schedule
@@ -23,13 +48,13 @@ impl fmt::Display for Schedule {
         match self.id {
             Some(i) => write!(
                 f,
-                "[id: {}, user: {}, cron: {}, url: {}]",
-                i, self.user, self.cron, self.url
+                "[id: {}, user: {}, cron: {}, url: {}, options: {}, target: {}, policy: {}]",
+                i, self.user, self.cron, self.url, self.options, self.target, self.policy
             ),
             None => write!(
                 f,
-                "[user: {}, cron: {}, url: {}]",
-                self.user, self.cron, self.url
+                "[user: {}, cron: {}, url: {}, options: {}, target: {}, policy: {}]",
+                self.user, self.cron, self.url, self.options, self.target, self.policy
             ),
         }
     }
@@ -42,6 +67,9 @@ impl Schedule {
         user: S,
         cron: S,
         url: S,
+        options: S,
+        target: S,
+        policy: S,
     ) -> Result<Self> {
         if cron::Schedule::from_str(cron.as_ref()).is_err() {
             return Err(anyhow!(
@@ -50,13 +78,54 @@ impl Schedule {
             ));
         };
         Url::from_str(url.as_ref())?;
+        if !options.as_ref().is_empty() {
+            keys::validate_options(&keys::parse_options(options.as_ref())?)?;
+        }
+        Target::from_str(target.as_ref())?;
+        Policy::from_str(policy.as_ref())?;
         Ok(Schedule {
             id,
             user: user.as_ref().to_string(),
             cron: cron.as_ref().to_string(),
             url: url.as_ref().to_string(),
+            options: options.as_ref().to_string(),
+            target: target.as_ref().to_string(),
+            policy: policy.as_ref().to_string(),
+            missing_count: 0,
         })
     }
+
+    /// Gets the parsed key options of the schedule
+    pub fn key_options(&self) -> Result<Vec<KeyOption>> {
+        if self.options.is_empty() {
+            return Ok(vec![]);
+        }
+        keys::parse_options(&self.options)
+    }
+
+    /// Gets the parsed target of the schedule
+    pub fn key_target(&self) -> Result<Target> {
+        Target::from_str(&self.target)
+    }
+
+    /// Gets the parsed key policy of the schedule
+    pub fn key_policy(&self) -> Result<Policy> {
+        Policy::from_str(&self.policy)
+    }
+
+    /// Gets the local users of the schedule, a group is resolved to its current members
+    pub fn users(&self) -> Result<Vec<String>> {
+        resolve_users(&self.user)
+    }
+}
+
+/// A key served to a job, and since when, used to hold new keys back for the quarantine period
+#[derive(Debug, PartialEq)]
+pub struct Pending {
+    pub job: u32,
+    pub fingerprint: String,
+    /// When the provider says the key was added, or else when the job first saw it
+    pub since: DateTime<Utc>,
 }
 
 /// Object representing a database
@@ -103,11 +172,63 @@ impl Database {
             user text not null,
             cron text not null,
             url text not null,
+            options text not null default '',
+            target text not null default 'home',
+            policy text not null default '',
+            missing_count integer not null default 0,
             unique (user, cron, url)
             )",
             [],
         )
         .context("Error initializing new database")?;
+
+        conn.execute(
+            "create table if not exists Pending (
+            job integer not null,
+            fingerprint text not null,
+            since text not null,
+            unique (job, fingerprint)
+            )",
+            [],
+        )
+        .context("Error initializing new database")?;
+
+        conn.execute(
+            "create table if not exists Mapped (
+            user text primary key
+            )",
+            [],
+        )
+        .context("Error initializing new database")?;
+
+        conn.execute(
+            "create table if not exists Account (
+            url text primary key,
+            account_id text not null
+            )",
+            [],
+        )
+        .context("Error initializing new database")?;
+
+        // Databases created by older versions need the newer columns added
+        for (column, definition) in COLUMNS {
+            if conn
+                .prepare(&format!("SELECT {} FROM Schedule", column))
+                .is_err()
+            {
+                conn.execute(
+                    &format!(
+                        "ALTER TABLE Schedule ADD COLUMN {} {}",
+                        column, definition
+                    ),
+                    [],
+                )
+                .with_context(|| {
+                    format!("Error adding {} to existing database", column)
+                })?;
+            }
+        }
+
         Ok(Database { connection: conn })
     }
 
@@ -115,6 +236,10 @@ impl Database {
     pub fn delete_schedule(&self, id: u32) -> Result<()> {
         self.connection
             .execute("DELETE FROM Schedule WHERE ID = ?1", params![id])
+            .and_then(|_| {
+                self.connection
+                    .execute("DELETE FROM Pending WHERE job = ?1", params![id])
+            })
             .with_context(|| {
                 format!("Error deleting database entry with id: {}", id)
             })?;
@@ -127,11 +252,22 @@ impl Database {
         user: S,
         cron: S,
         url: S,
+        options: S,
+        target: S,
+        policy: S,
     ) -> Result<bool> {
-        let schedule = Schedule::new(None, user, cron, url)?;
+        let schedule =
+            Schedule::new(None, user, cron, url, options, target, policy)?;
         let result: Result<usize, Error> = self.connection.execute(
-            "INSERT INTO Schedule (user, cron, url) VALUES (?1, ?2, ?3)",
-            params![schedule.user, schedule.cron, schedule.url],
+            "INSERT INTO Schedule (user, cron, url, options, target, policy) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                schedule.user,
+                schedule.cron,
+                schedule.url,
+                schedule.options,
+                schedule.target,
+                schedule.policy
+            ],
         );
 
         match result {
@@ -147,25 +283,195 @@ impl Database {
         }
     }
 
-    /// Gets a list of schedules from the database
-    pub fn get_schedules(&self) -> Result<Vec<Schedule>> {
+    /// Pins a keys url to the numeric ID of its remote account
+    pub fn pin_account(&self, url: &str, account_id: &str) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR REPLACE INTO Account (url, account_id) VALUES (?1, ?2)",
+                params![url, account_id],
+            )
+            .with_context(|| {
+                format!("Error pinning the account of: {}", url)
+            })?;
+        Ok(())
+    }
+
+    /// Gets the numeric ID of the remote account a keys url is pinned to, None if it is not pinned yet
+    pub fn pinned_account(&self, url: &str) -> Result<Option<String>> {
+        self.connection
+            .query_row(
+                "SELECT account_id FROM Account WHERE url = ?1",
+                params![url],
+                |row| row.get(0),
+            )
+            .optional()
+            .with_context(|| {
+                format!("Error reading the pinned account of: {}", url)
+            })
+    }
+
+    /// Records that apply wrote keys of the mapping file for a local user
+    pub fn add_mapped_user(&self, user: &str) -> Result<()> {
+        self.connection
+            .execute(
+                "INSERT OR IGNORE INTO Mapped (user) VALUES (?1)",
+                params![user],
+            )
+            .with_context(|| {
+                format!("Error recording mapped user: {}", user)
+            })?;
+        Ok(())
+    }
+
+    /// Forgets a local user apply no longer writes keys for
+    pub fn remove_mapped_user(&self, user: &str) -> Result<()> {
+        self.connection
+            .execute("DELETE FROM Mapped WHERE user = ?1", params![user])
+            .with_context(|| {
+                format!("Error forgetting mapped user: {}", user)
+            })?;
+        Ok(())
+    }
+
+    /// Gets the local users apply has written keys of the mapping file for
+    pub fn mapped_users(&self) -> Result<Vec<String>> {
+        let mut stmt = self
+            .connection
+            .prepare("SELECT user FROM Mapped ORDER BY user")?;
+        let users = stmt
+            .query_map([], |row| row.get(0))?
+            .collect::<Result<Vec<String>, _>>()
+            .context("Error reading mapped users")?;
+        Ok(users)
+    }
+
+    /// Sets how many runs in a row found the remote account of a schedule missing
+    pub fn set_missing_count(&self, id: u32, count: u32) -> Result<()> {
+        self.connection
+            .execute(
+                "UPDATE Schedule SET missing_count = ?2 WHERE ID = ?1",
+                params![id, count],
+            )
+            .with_context(|| {
+                format!(
+                    "Error updating the missing count of job with id: {}",
+                    id
+                )
+            })?;
+        Ok(())
+    }
+
+    /// Gets since when a job has been served a key, recording the given time if it is new
+    pub fn key_since(
+        &self,
+        job: u32,
+        fingerprint: &str,
+        since: DateTime<Utc>,
+    ) -> Result<DateTime<Utc>> {
+        self.connection
+            .execute(
+                "INSERT OR IGNORE INTO Pending (job, fingerprint, since) VALUES (?1, ?2, ?3)",
+                params![job, fingerprint, since.to_rfc3339()],
+            )
+            .and_then(|_| {
+                self.connection.query_row(
+                    "SELECT since FROM Pending WHERE job = ?1 AND fingerprint = ?2",
+                    params![job, fingerprint],
+                    |row| row.get::<_, String>(0),
+                )
+            })
+            .map_err(|e| e.into())
+            .and_then(|x| parse_since(&x))
+            .with_context(|| {
+                format!("Error recording key {} of job {}", fingerprint, job)
+            })
+    }
+
+    /// Forgets the keys a job is no longer served, so they are held back again if they come back
+    pub fn forget_keys(&self, job: u32, served: &[String]) -> Result<()> {
+        for key in self.get_pending()?.iter().filter(|x| x.job == job) {
+            if !served.contains(&key.fingerprint) {
+                self.connection
+                    .execute(
+                        "DELETE FROM Pending WHERE job = ?1 AND fingerprint = ?2",
+                        params![job, key.fingerprint],
+                    )
+                    .with_context(|| {
+                        format!("Error forgetting key {}", key.fingerprint)
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Gets every key served to a job, with since when
+    pub fn get_pending(&self) -> Result<Vec<Pending>> {
         let mut stmt = self
             .connection
-            .prepare("SELECT id, user, cron, url FROM Schedule")?;
+            .prepare("SELECT job, fingerprint, since FROM Pending")?;
+        let pending_iter = stmt.query_map(params![], |row| {
+            Ok((row.get(0)?, row.get(1)?, row.get(2)?))
+        })?;
+
+        Ok(pending_iter
+            .filter_map(|x| x.ok())
+            .map(|x: (u32, String, String)| {
+                Ok(Pending {
+                    job: x.0,
+                    fingerprint: x.1,
+                    since: parse_since(&x.2)?,
+                })
+            })
+            .filter_map(|x: Result<Pending>| x.ok())
+            .collect())
+    }
+
+    /// Gets a list of schedules from the database
+    pub fn get_schedules(&self) -> Result<Vec<Schedule>> {
+        let mut stmt = self.connection.prepare(
+            "SELECT id, user, cron, url, options, target, policy, missing_count FROM Schedule",
+        )?;
         let schedule_iter = stmt.query_map(params![], |row| {
-            Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?))
+            Ok((
+                (
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                    row.get(5)?,
+                    row.get(6)?,
+                ),
+                row.get(7)?,
+            ))
         })?;
 
         Ok(schedule_iter
             .filter_map(|x| x.ok())
-            .map(|x: (u32, String, String, String)| {
-                Schedule::new(Some(x.0), x.1, x.2, x.3)
+            .map(|(x, missing_count): (Columns, u32)| {
+                Ok(Schedule {
+                    missing_count,
+                    ..Schedule::new(Some(x.0), x.1, x.2, x.3, x.4, x.5, x.6)?
+                })
             })
-            .filter_map(|x| x.ok())
+            .filter_map(|x: Result<Schedule>| x.ok())
             .collect())
     }
 }
 
+/// Parses the time a key was first served, as stored
+fn parse_since(since: &str) -> Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(since)?.with_timezone(&Utc))
+}
+
+/// Gets the local users a user or %group stands for, a group is resolved to its current members
+pub fn resolve_users(user: &str) -> Result<Vec<String>> {
+    match user.strip_prefix(GROUP_PREFIX) {
+        Some(group) => util::group_members(group),
+        None => Ok(vec![user.to_owned()]),
+    }
+}
+
 /// Last modified for the default database path
 pub fn db_last_modified() -> Result<FileTime> {
     let path = PathBuf::from("/usr/share/keysync/schedule.db");
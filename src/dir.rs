@@ -0,0 +1,354 @@
+use anyhow::{anyhow, Context, Error, Result};
+use nix::errno::Errno;
+use nix::fcntl::{self, OFlag};
+use nix::sys::stat::{self, FileStat, Mode, SFlag};
+use nix::unistd::{self, Gid, Uid, UnlinkatFlags};
+use std::fs;
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::path::{Component, Path, PathBuf};
+
+/// The owner and mode given to directories keysync creates
+pub type NewDir = ((Uid, Gid), u32);
+
+/// A directory held open, the files in it are read, created and renamed relative to it
+/// Since root follows symbolic links, a directory swapped for one after it was checked would otherwise be written through
+#[derive(Debug)]
+pub struct Dir {
+    fd: nix::dir::Dir,
+    path: PathBuf,
+}
+
+impl Dir {
+    /// Opens a directory, refusing a symbolic link as any component below the trusted directory, or as the directory itself
+    /// The trusted directory, Ex: the home directory, may be a symbolic link, returns None if a component does not exist
+    pub fn open(path: &Path, trusted: Option<&Path>) -> Result<Option<Self>> {
+        Dir::open_with(path, trusted, None)
+    }
+
+    /// Opens a directory like open, first creating the missing directories with the owner and mode
+    pub fn create(
+        path: &Path,
+        trusted: Option<&Path>,
+        new: NewDir,
+    ) -> Result<Self> {
+        Dir::open_with(path, trusted, Some(new))?.with_context(|| {
+            format!("Failed to create the directory [{}]", path.display())
+        })
+    }
+
+    /// Opens a directory from the trusted directory one component at a time, creating missing ones if asked to
+    fn open_with(
+        path: &Path,
+        trusted: Option<&Path>,
+        new: Option<NewDir>,
+    ) -> Result<Option<Self>> {
+        let below = trusted.and_then(|t| Some((t, path.strip_prefix(t).ok()?)));
+        let (trusted, rest) = match below {
+            Some(x) => x,
+            None => return Dir::open_untrusted(path, new),
+        };
+        let mut dir = match nix::dir::Dir::open(
+            trusted,
+            OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_CLOEXEC,
+            Mode::empty(),
+        ) {
+            Ok(d) => Dir {
+                fd: d,
+                path: trusted.to_path_buf(),
+            },
+            Err(Errno::ENOENT) => return Ok(None),
+            Err(e) => {
+                return Err(Error::new(e).context(format!(
+                    "Failed to open the directory [{}]",
+                    trusted.display()
+                )))
+            }
+        };
+        for component in rest.components() {
+            let name = match component {
+                Component::Normal(n) => n.to_string_lossy().to_string(),
+                Component::CurDir => continue,
+                _ => {
+                    return Err(anyhow!(
+                        "Refusing to use the path [{}], it leaves [{}]",
+                        path.display(),
+                        trusted.display()
+                    ))
+                }
+            };
+            dir = match dir.subdir(&name, new)? {
+                Some(d) => d,
+                None => return Ok(None),
+            };
+        }
+        Ok(Some(dir))
+    }
+
+    /// Opens a directory that is not below a trusted one, only the directory itself is not followed
+    /// Missing directories are created by path, the directories above it belong to root
+    fn open_untrusted(
+        path: &Path,
+        new: Option<NewDir>,
+    ) -> Result<Option<Self>> {
+        let created = match (new, path.exists()) {
+            (Some(_), false) => {
+                fs::create_dir_all(path).with_context(|| {
+                    format!(
+                        "Failed to create the directory [{}]",
+                        path.display()
+                    )
+                })?;
+                true
+            }
+            _ => false,
+        };
+        let dir = match nix::dir::Dir::open(path, dir_flags(), Mode::empty()) {
+            Ok(d) => Dir {
+                fd: d,
+                path: path.to_path_buf(),
+            },
+            Err(Errno::ENOENT) => return Ok(None),
+            Err(e) => return Err(open_error(e, path)),
+        };
+        if let Some(((uid, gid), mode)) = new.filter(|_| created) {
+            dir.set_owner(uid, gid, mode)?;
+        }
+        Ok(Some(dir))
+    }
+
+    /// The path the directory was opened with
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Opens a directory in this one without following a symbolic link, creating it with the owner and mode if asked to
+    /// Returns None if it does not exist and is not created
+    pub fn subdir(
+        &self,
+        name: &str,
+        new: Option<NewDir>,
+    ) -> Result<Option<Dir>> {
+        let path = self.path.join(name);
+        let mut created = false;
+        loop {
+            match nix::dir::Dir::openat(
+                self.fd(),
+                name,
+                dir_flags(),
+                Mode::empty(),
+            ) {
+                Ok(d) => {
+                    let dir = Dir { fd: d, path };
+                    if let Some(((uid, gid), mode)) = new.filter(|_| created) {
+                        dir.set_owner(uid, gid, mode)?;
+                    }
+                    return Ok(Some(dir));
+                }
+                Err(Errno::ENOENT) => (),
+                Err(e) => return Err(open_error(e, &path)),
+            }
+            let mode = match new {
+                Some((_, m)) if !created => m,
+                _ => return Ok(None),
+            };
+            stat::mkdirat(self.fd(), name, Mode::from_bits_truncate(mode))
+                .with_context(|| {
+                    format!(
+                        "Failed to create the directory [{}]",
+                        path.display()
+                    )
+                })?;
+            created = true;
+        }
+    }
+
+    /// Gets the status of a file in the directory without following a symbolic link, None if it does not exist
+    pub fn stat(&self, name: &str) -> Result<Option<FileStat>> {
+        match stat::fstatat(
+            self.fd(),
+            name,
+            fcntl::AtFlags::AT_SYMLINK_NOFOLLOW,
+        ) {
+            Ok(s) => Ok(Some(s)),
+            Err(Errno::ENOENT) => Ok(None),
+            Err(e) => Err(Error::new(e).context(format!(
+                "Error reading metadata of: {}",
+                self.path.join(name).display()
+            ))),
+        }
+    }
+
+    /// Opens a file in the directory for reading, refusing a symbolic link, None if it does not exist
+    pub fn open_file(&self, name: &str) -> Result<Option<OpenFile>> {
+        let flags = OFlag::O_RDONLY
+            | OFlag::O_NOFOLLOW
+            | OFlag::O_NONBLOCK
+            | OFlag::O_CLOEXEC;
+        match fcntl::openat(self.fd(), name, flags, Mode::empty()) {
+            Ok(fd) => Ok(Some(OpenFile(fd))),
+            Err(Errno::ENOENT) => Ok(None),
+            Err(e) => Err(open_error(e, &self.path.join(name))),
+        }
+    }
+
+    /// Creates a new file in the directory with the mode, failing with EEXIST if the name is taken, even by a symbolic link
+    pub fn create_file(&self, name: &str, mode: u32) -> nix::Result<OpenFile> {
+        let flags = OFlag::O_WRONLY
+            | OFlag::O_CREAT
+            | OFlag::O_EXCL
+            | OFlag::O_NOFOLLOW
+            | OFlag::O_CLOEXEC;
+        fcntl::openat(self.fd(), name, flags, Mode::from_bits_truncate(mode))
+            .map(OpenFile)
+    }
+
+    /// Renames a file in the directory, replacing the file (or symbolic link) with the new name
+    pub fn rename(&self, from: &str, to: &str) -> Result<()> {
+        fcntl::renameat(Some(self.fd()), from, Some(self.fd()), to)
+            .with_context(|| {
+                format!(
+                    "Error renaming [{}] to [{}]",
+                    self.path.join(from).display(),
+                    self.path.join(to).display()
+                )
+            })
+    }
+
+    /// Removes a file from the directory
+    pub fn remove(&self, name: &str) -> Result<()> {
+        unistd::unlinkat(Some(self.fd()), name, UnlinkatFlags::NoRemoveDir)
+            .with_context(|| {
+                format!("Failed to remove [{}]", self.path.join(name).display())
+            })
+    }
+
+    /// Gets the names of the regular files in the directory
+    pub fn files(&mut self) -> Result<Vec<String>> {
+        let path = self.path.clone();
+        let mut names: Vec<String> = vec![];
+        for entry in self.fd.iter() {
+            let entry = entry.with_context(|| {
+                format!("Failed to read the directory [{}]", path.display())
+            })?;
+            let name = entry.file_name().to_string_lossy().to_string();
+            if name == "." || name == ".." {
+                continue;
+            }
+            names.push(name);
+        }
+        let mut files: Vec<String> = vec![];
+        for name in names {
+            if self.stat(&name)?.is_some_and(|x| is_file(&x)) {
+                files.push(name);
+            }
+        }
+        Ok(files)
+    }
+
+    /// Syncs the directory to disk, so renames in it are durable
+    pub fn sync(&self) -> Result<()> {
+        unistd::fsync(self.fd()).with_context(|| {
+            format!("Error syncing directory: {}", self.path.display())
+        })
+    }
+
+    /// Sets the owner and mode of the directory
+    fn set_owner(&self, uid: Uid, gid: Gid, mode: u32) -> Result<()> {
+        unistd::fchown(self.fd(), Some(uid), Some(gid))
+            .and_then(|_| {
+                stat::fchmod(self.fd(), Mode::from_bits_truncate(mode))
+            })
+            .with_context(|| {
+                format!(
+                    "Failed to set the directory [{}] ownership to user",
+                    self.path.display()
+                )
+            })
+    }
+
+    fn fd(&self) -> RawFd {
+        self.fd.as_raw_fd()
+    }
+}
+
+/// A file opened relative to a Dir, closed when dropped
+#[derive(Debug)]
+pub struct OpenFile(RawFd);
+
+impl OpenFile {
+    /// Gets the status of the open file
+    pub fn stat(&self) -> Result<FileStat> {
+        Ok(stat::fstat(self.0)?)
+    }
+
+    /// Reads the rest of the file
+    pub fn read_to_end(&self) -> Result<Vec<u8>> {
+        let mut content: Vec<u8> = vec![];
+        let mut buffer = [0u8; 8192];
+        loop {
+            match unistd::read(self.0, &mut buffer) {
+                Ok(0) => return Ok(content),
+                Ok(n) => content.extend_from_slice(&buffer[..n]),
+                Err(Errno::EINTR) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Writes all of the data to the file
+    pub fn write_all(&self, data: &[u8]) -> Result<()> {
+        let mut rest = data;
+        while !rest.is_empty() {
+            match unistd::write(self.0, rest) {
+                Ok(n) => rest = &rest[n..],
+                Err(Errno::EINTR) => (),
+                Err(e) => return Err(e.into()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Sets the owner and mode of the file
+    pub fn set_owner(&self, uid: Uid, gid: Gid, mode: u32) -> Result<()> {
+        unistd::fchown(self.0, Some(uid), Some(gid))?;
+        stat::fchmod(self.0, Mode::from_bits_truncate(mode))?;
+        Ok(())
+    }
+
+    /// Syncs the file to disk
+    pub fn sync(&self) -> Result<()> {
+        Ok(unistd::fsync(self.0)?)
+    }
+}
+
+impl Drop for OpenFile {
+    fn drop(&mut self) {
+        let _ = unistd::close(self.0);
+    }
+}
+
+/// Returns true if the status is of a regular file
+pub fn is_file(stat: &FileStat) -> bool {
+    SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT == SFlag::S_IFREG
+}
+
+/// The flags directories are opened with, a symbolic link is never followed
+fn dir_flags() -> OFlag {
+    OFlag::O_RDONLY | OFlag::O_DIRECTORY | OFlag::O_NOFOLLOW | OFlag::O_CLOEXEC
+}
+
+/// Explains why a path could not be opened, a symbolic link is refused
+fn open_error(error: Errno, path: &Path) -> Error {
+    match error {
+        Errno::ELOOP => {
+            anyhow!("Refusing to use the symbolic link: {}", path.display())
+        }
+        e => Error::new(e)
+            .context(format!("Failed to open [{}]", path.display())),
+    }
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/dir.rs"]
+mod test;
@@ -1,16 +1,37 @@
-use anyhow::{Context, Result};
-use log::info;
-use nix::unistd::{chown, Gid, Uid, User};
-use std::io::Write;
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::Utc;
+use log::{debug, info, warn};
+use nix::errno::Errno;
+use nix::fcntl::{flock, FlockArg};
+use nix::sys::stat::FileStat;
+use nix::unistd::{Gid, Uid, User};
+use std::os::unix::fs::{DirBuilderExt, OpenOptionsExt};
+use std::os::unix::io::AsRawFd;
 use std::path::{Path, PathBuf};
-use std::{fs, fs::File};
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use std::{fmt, fs, fs::File};
 
+use super::backup::{Backup, Backups};
+use super::change::{Change, Conflict, Reason, Removal};
+use super::config::{Config, OnConflict};
+use super::dir::{self, Dir, OpenFile};
+use super::keys::{self, AuthorizedKey, KeyOption};
+use super::perms::{self, Expected, Problem};
+use super::provenance;
+use super::sshd::SshdConfig;
 use super::util;
 
 // Authorized keys file implementation
 #[derive(Debug)]
 pub struct AuthorizedKeys {
     path: PathBuf,
+    backups: usize,
+    expected: Option<Expected>,
+    fix_permissions: bool,
+    on_conflict: OnConflict,
+    /// True for sshd's TrustedUserCAKeys file, which does not take key options
+    trusted_ca: bool,
 }
 
 impl AuthorizedKeys {
@@ -22,114 +43,768 @@ impl AuthorizedKeys {
             File::create(&path)
                 .context("Failed to create the AuthorizedKeys file")?;
         }
-        Ok(AuthorizedKeys { path })
+        Ok(AuthorizedKeys {
+            path,
+            backups: Config::default().backups,
+            expected: None,
+            fix_permissions: false,
+            on_conflict: OnConflict::Report,
+            trusted_ca: false,
+        })
     }
 
-    /// Sets up the AuthorizedKeys object
-    pub fn open<S: AsRef<str>>(user: Option<S>) -> Result<Self> {
-        let ids: (Uid, Gid) = get_uid_gid(user.as_ref())?;
-        // TODO support non default home dir
-        let home_dir = if let Some(u) = user {
-            PathBuf::from("/home").join(u.as_ref())
-        } else {
-            dirs::home_dir().context("Failed to get home directory")?
-        };
+    /// Sets up the AuthorizedKeys object for the user and target, creating the file if needed
+    pub fn open<S: AsRef<str>>(
+        user: Option<S>,
+        target: &Target,
+        config: &Config,
+    ) -> Result<Self> {
+        let authorized_keys = AuthorizedKeys::find(user, target, config)?;
+        if !authorized_keys.path.is_file() {
+            if let Some(expected) = &authorized_keys.expected {
+                create_file(&authorized_keys.path, expected)?;
+            }
+        }
+        Ok(authorized_keys)
+    }
 
-        let path = home_dir.join(".ssh").join("authorized_keys");
+    /// Sets up the AuthorizedKeys object for the user and target without creating anything, for reading
+    /// A file that does not exist yet reads as empty
+    pub fn find<S: AsRef<str>>(
+        user: Option<S>,
+        target: &Target,
+        config: &Config,
+    ) -> Result<Self> {
+        let user: User = get_user(user)?;
+        let sshd_config = SshdConfig::load(&config.sshd_config)?;
+        let sshd_files: Vec<PathBuf> =
+            sshd_config.authorized_keys_files(&user)?;
 
-        // Create the authorized keys file or path
-        if !path.is_file() {
-            if let Some(file_path) = path.parent() {
-                if !file_path.is_dir() {
-                    fs::create_dir_all(file_path).with_context(|| {
-                        format!("Failed to create the directory [{}] for the authorized_keys file", file_path.display())
-                    })?;
-                    chown(file_path, Some(ids.0), Some(ids.1)).with_context(|| {
-                        format!("Failed to set the folder [{}] ownership to user", file_path.display())
-                    })?;
+        let path: PathBuf = match target {
+            // Use the first file sshd reads for the user
+            Target::Home => {
+                sshd_files.into_iter().next().with_context(|| {
+                    format!(
+                        "AuthorizedKeysFile is set to none in {}",
+                        config.sshd_config.display()
+                    )
+                })?
+            }
+            Target::Central(dir) => {
+                let path = dir.join(&user.name);
+                if !sshd_files.contains(&path) {
+                    warn!(
+                        "sshd does not read {}, add {}/%u to AuthorizedKeysFile in {}",
+                        path.display(),
+                        dir.display(),
+                        config.sshd_config.display()
+                    );
                 }
+                path
             }
-            File::create(&path).with_context(|| {
-                format!(
-                    "Failed to create the authorized_keys [{}] file",
-                    path.display()
-                )
-            })?;
-            chown(&path, Some(ids.0), Some(ids.1)).with_context(|| {
-                format!(
-                    "Failed to set authorized_keys [{}] ownership to user",
-                    path.display()
-                )
-            })?;
+            Target::Cache(dir) => dir.join(&user.name),
+            Target::TrustedCa(path) => {
+                if sshd_config.trusted_user_ca_keys().as_ref() != Some(path) {
+                    warn!(
+                        "sshd does not read {}, set TrustedUserCAKeys {} in {}",
+                        path.display(),
+                        path.display(),
+                        config.sshd_config.display()
+                    );
+                }
+                path.clone()
+            }
+        };
+        debug!("Using authorized_keys file {}", path.display());
+
+        let expected: Expected = match target {
+            Target::Home => Expected::user(&user, &path),
+            Target::Central(_) | Target::Cache(_) => Expected::central(&user),
+            Target::TrustedCa(_) => Expected::trusted_ca(&user),
+        };
+
+        Ok(AuthorizedKeys {
+            path,
+            backups: config.backups,
+            expected: Some(expected),
+            fix_permissions: config.fix_permissions,
+            on_conflict: config.on_conflict,
+            trusted_ca: matches!(target, Target::TrustedCa(_)),
+        })
+    }
+
+    /// The path of the authorized keys file
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Checks the permissions and ownership of the file and the directories above it
+    pub fn audit(&self) -> Result<Vec<Problem>> {
+        match &self.expected {
+            Some(e) => perms::audit(&self.path, e),
+            None => Ok(vec![]),
+        }
+    }
+
+    /// Sets the expected permissions and ownership on the file and its directory
+    pub fn repair(&self) -> Result<()> {
+        match &self.expected {
+            Some(e) => perms::repair(&self.path, e),
+            None => Ok(()),
         }
+    }
+
+    /// Gets the backups of the authorized keys file
+    pub fn backups(&self) -> Backups {
+        Backups::new(&self.path, self.backups)
+    }
+
+    /// Restores the authorized keys file from a backup, the current contents are backed up first
+    pub fn restore(&self, backup: &Backup) -> Result<()> {
+        let _lock = self.lock()?;
+        let content = backup.read()?;
+        self.replace(&content)
+    }
 
-        Ok(AuthorizedKeys { path })
+    /// Takes an exclusive advisory lock so concurrent keysync processes serialize their changes, released when dropped
+    /// Gives up after a timeout rather than waiting forever on a lock someone else holds
+    pub fn lock(&self) -> Result<FileLock> {
+        let (file, name) = self.lock_file()?;
+        debug!("Waiting for lock on {}", name.display());
+        lock_exclusive(file, &name, LOCK_TIMEOUT)
+    }
+
+    /// Opens the file to lock, a root owned file under the lock directory for user files when running as root
+    /// Otherwise the lock is held on the containing directory since the file itself is replaced on every write
+    fn lock_file(&self) -> Result<(File, PathBuf)> {
+        if self.expected.is_some() {
+            let name = Path::new(LOCK_DIR).join(format!(
+                "{}.lock",
+                self.path.to_string_lossy().replace('/', "%")
+            ));
+            match open_lock_file(&name) {
+                Ok(Some(file)) => return Ok((file, name)),
+                Ok(None) => (),
+                Err(e) => warn!("{:#}, locking the directory instead", e),
+            }
+        }
+        let dir = self.dir();
+        let file = File::open(&dir).with_context(|| {
+            format!("Failed to open [{}] for locking", dir.display())
+        })?;
+        Ok((file, dir))
     }
 
-    /// Gets array of current authorized keys, and true if the keys file ends with a newline, false otherwise
-    fn get_keys(&self) -> Result<(Vec<String>, bool)> {
+    /// Reads the raw contents of the authorized keys file, empty if it does not exist yet
+    /// A symbolic link is refused, so root is never made to reveal the file it points at
+    fn read(&self) -> Result<String> {
         info!("Reading keys to {}", self.path.display());
-        let keys_string =
-            fs::read_to_string(&self.path).with_context(|| {
+        let file = match self.open_dir()? {
+            Some(dir) => dir.open_file(&self.file_name()?)?,
+            None => None,
+        };
+        let file = match file {
+            Some(f) => f,
+            None => return Ok(String::new()),
+        };
+        if !dir::is_file(&file.stat()?) {
+            return Err(anyhow!(
+                "Refusing to read {}, it is not a regular file",
+                self.path.display()
+            ));
+        }
+        file.read_to_end()
+            .and_then(|x| Ok(String::from_utf8(x)?))
+            .with_context(|| {
                 format!("Error reading keys from file: {}", self.path.display())
-            })?;
-        let keys = util::clean_keys(util::split_keys(&keys_string));
-        let ends_with_newline = keys_string.ends_with('\n');
-        Ok((keys, ends_with_newline))
+            })
     }
 
-    /// Writes array of keys to authorized keys file, returns amount of keys to write or written
+    /// Atomically replaces the contents of the authorized keys file, keeping its owner and mode
+    /// The previous contents are backed up, then the new contents are written to a new file in the same directory,
+    /// synced to disk, and renamed over the original
+    /// Everything is done relative to the open directory, so it can not be swapped for a symbolic link in the meantime
+    /// Callers should hold the lock while reading the content and calling this
+    fn replace(&self, content: &str) -> Result<()> {
+        let dir = self.open_dir()?.with_context(|| {
+            format!("The directory of {} does not exist", self.path.display())
+        })?;
+        let name = self.file_name()?;
+        let original = match dir.stat(&name)? {
+            Some(s) if dir::is_file(&s) => s,
+            Some(_) => {
+                return Err(anyhow!(
+                    "Refusing to replace {}, it is not a regular file",
+                    self.path.display()
+                ))
+            }
+            None => {
+                return Err(anyhow!(
+                    "Error reading metadata of: {}",
+                    self.path.display()
+                ))
+            }
+        };
+        self.backups().create(&dir)?;
+
+        // Created exclusively, so an existing file or symbolic link is never opened
+        let (tmp_name, tmp) = create_temp(&dir, &name)?;
+        let replaced = write_synced(&tmp, content, &original)
+            .and_then(|_| dir.rename(&tmp_name, &name));
+        if let Err(e) = replaced {
+            let _ = dir.remove(&tmp_name);
+            return Err(e.context(format!(
+                "Error replacing keys file: {}",
+                self.path.display()
+            )));
+        }
+
+        // Make sure the rename itself is durable
+        dir.sync()?;
+
+        self.check_permissions()
+    }
+
+    /// Opens the directory containing the file, refusing symbolic links below the home directory, None if it does not exist
+    fn open_dir(&self) -> Result<Option<Dir>> {
+        let home = self.expected.as_ref().map(|x| x.home.as_path());
+        Dir::open(&self.dir(), home)
+    }
+
+    /// The file name of the authorized keys file
+    fn file_name(&self) -> Result<String> {
+        self.path
+            .file_name()
+            .map(|x| x.to_string_lossy().to_string())
+            .context("The authorized_keys path has no file name")
+    }
+
+    /// Checks the permissions after a write, repairing them if configured to, otherwise warning about each problem
+    fn check_permissions(&self) -> Result<()> {
+        let problems = self.audit()?;
+        if problems.is_empty() {
+            return Ok(());
+        }
+        if self.fix_permissions {
+            self.repair()?;
+            for problem in self.audit()? {
+                warn!("{}", problem);
+            }
+        } else {
+            for problem in problems {
+                warn!("{}", problem);
+            }
+            warn!("Run keysync doctor --fix to repair the permissions");
+        }
+        Ok(())
+    }
+
+    /// The directory containing the authorized keys file
+    fn dir(&self) -> PathBuf {
+        match self.path.parent() {
+            Some(p) if !p.as_os_str().is_empty() => p.to_path_buf(),
+            _ => PathBuf::from("."),
+        }
+    }
+
+    /// Gets the keys currently in the authorized keys file, in file order
+    pub fn keys(&self) -> Result<Vec<AuthorizedKey>> {
+        Ok(keys::parse(&self.read()?))
+    }
+
+    /// Removes the keys the selector matches, given the index of the key (starting at 1) and the key
+    /// Every other line is kept as is, returns the change to make or made
+    pub fn remove_keys<F: Fn(usize, &AuthorizedKey) -> bool>(
+        &self,
+        selector: F,
+        dry_run: bool,
+    ) -> Result<Change> {
+        let _lock = self.lock()?;
+        let original = self.read()?;
+        let (content, removed) = remove_lines(&original, selector);
+
+        let change = self.change(original, content, vec![], removed);
+        if change.is_empty() || dry_run {
+            return Ok(change);
+        }
+        info!(
+            "Removing {} keys from {}",
+            change.removed.len(),
+            self.path.display()
+        );
+        self.replace(&change.after)?;
+        Ok(change)
+    }
+
+    /// Rewrites the file without duplicate keys, expired keys keysync imported, malformed lines and stray whitespace, returns the change to make or made
+    /// The first copy of a key is kept as sshd uses it, later copies with the same options are removed whatever their comment
+    /// Copies with other options are kept and reported, the reason each line is removed is in the removals of the change
+    pub fn tidy(&self, dry_run: bool) -> Result<Change> {
+        let _lock = self.lock()?;
+        let original = self.read()?;
+        let now = Utc::now();
+        let mut lines: Vec<String> = vec![];
+        let mut kept: Vec<AuthorizedKey> = vec![];
+        let mut removals: Vec<Removal> = vec![];
+        for (number, line) in original.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() {
+                // Only keep single blank lines between other lines
+                if lines.last().is_some_and(|x| !x.is_empty()) {
+                    lines.push(String::new());
+                }
+                continue;
+            }
+            if line.starts_with('#') {
+                lines.push(line.to_owned());
+                continue;
+            }
+            let mut removal = Removal {
+                reason: Reason::Malformed,
+                line: number + 1,
+                text: line.to_owned(),
+                key: None,
+            };
+            let key = match line.parse::<AuthorizedKey>() {
+                Ok(k) => k,
+                Err(e) => {
+                    debug!("Line {} is not a key: {}", number + 1, e);
+                    removals.push(removal);
+                    continue;
+                }
+            };
+            let first = kept.iter().find(|x| x.same_key(&key));
+            if provenance::is_managed(&key) && key.is_expired(now) {
+                removal.reason = Reason::Expired;
+            } else if first
+                .is_some_and(|x| keys::same_options(&x.options, &key.options))
+            {
+                removal.reason = Reason::Duplicate;
+            } else {
+                if first.is_some() {
+                    warn!(
+                        "Keeping line {}, {} is already in the file with other options, sshd uses the first",
+                        number + 1,
+                        key.fingerprint
+                    );
+                }
+                lines.push(key.to_string());
+                kept.push(key);
+                continue;
+            }
+            removal.key = Some(key);
+            removals.push(removal);
+        }
+        if lines.last().is_some_and(|x| x.is_empty()) {
+            lines.pop();
+        }
+        let content = lines.iter().map(|x| format!("{}\n", x)).collect();
+
+        let mut change = self.change(original, content, vec![], vec![]);
+        change.removals = removals;
+        if change.is_empty() || dry_run {
+            return Ok(change);
+        }
+        info!("Tidying {}", self.path.display());
+        self.replace(&change.after)?;
+        Ok(change)
+    }
+
+    /// Writes array of keys to authorized keys file with the given options prepended, returns the change to make or made
+    /// An expiry set by the provider is kept if it is before the one in the options
+    /// Managed keys that have expired, or that the provider says have expired, are removed, and keys that would be expired already are not added
+    /// Keys written to the TrustedUserCAKeys file never have options
     pub fn write_keys(
         &self,
-        keys: Vec<String>,
+        keys: Vec<AuthorizedKey>,
+        options: &[KeyOption],
         dry_run: bool,
-    ) -> Result<usize> {
-        let (existing_keys, ends_with_newline) = self.get_keys()?;
+    ) -> Result<Change> {
+        self.write(keys, options, None, dry_run)
+    }
+
+    /// Writes the keys of a job like write_keys, also removing the keys the job imported before that it no longer serves
+    /// Used for files keysync owns, so a key revoked at the provider is gone after the next run
+    pub fn replace_job_keys(
+        &self,
+        job: u32,
+        keys: Vec<AuthorizedKey>,
+        options: &[KeyOption],
+        dry_run: bool,
+    ) -> Result<Change> {
+        let from_job = |key: &AuthorizedKey| provenance::is_from_job(key, job);
+        self.write(keys, options, Some(&from_job), dry_run)
+    }
+
+    /// Writes the keys of the mapping file like write_keys, also removing the keys apply imported before that are no longer mapped
+    /// Keys of identities dropped from the mapping, and keys rotated at the provider, are gone after the next apply
+    pub fn replace_mapped_keys(
+        &self,
+        keys: Vec<AuthorizedKey>,
+        dry_run: bool,
+    ) -> Result<Change> {
+        self.write(keys, &[], Some(&provenance::is_mapped), dry_run)
+    }
+
+    /// Writes the keys, removing the keys no longer served that the replace function selects, if given
+    fn write(
+        &self,
+        keys: Vec<AuthorizedKey>,
+        options: &[KeyOption],
+        replace: Option<&dyn Fn(&AuthorizedKey) -> bool>,
+        dry_run: bool,
+    ) -> Result<Change> {
+        let _lock = self.lock()?;
+        let original = self.read()?;
+        let now = Utc::now();
+        let (kept, removed) = remove_lines(&original, |_, key| {
+            let revoked = replace.is_some_and(|owned| {
+                owned(key) && !keys.iter().any(|x| x.same_key(key))
+            });
+            revoked
+                || provenance::is_managed(key)
+                    && (key.is_expired(now)
+                        || keys
+                            .iter()
+                            .any(|x| x.is_expired(now) && x.same_key(key)))
+        });
+        let existing_keys = keys::parse(&kept);
+        let ends_with_newline = kept.is_empty() || kept.ends_with('\n');
+        let mut keys = keys;
+        for key in keys.iter_mut() {
+            keys::apply_options(key, options);
+        }
+        keys.retain(|x| !x.is_expired(now));
+        if self.trusted_ca {
+            // sshd would not parse a line with options, so an expiry from the provider is dropped
+            for key in keys.iter_mut() {
+                key.options.clear();
+            }
+        }
+
+        let conflicts = find_conflicts(&existing_keys, &keys);
+        if self.on_conflict == OnConflict::Refuse && !conflicts.is_empty() {
+            let reasons: Vec<String> =
+                conflicts.iter().map(|x| x.to_string()).collect();
+            return Err(anyhow!(
+                "Refusing to write keys to {}\n{}",
+                self.path.display(),
+                reasons.join("\n")
+            ));
+        }
         let keys_to_add = util::filter_keys(keys, existing_keys);
 
         info!("Writing keys to {}", self.path.display());
 
+        let content = if keys_to_add.is_empty() {
+            kept
+        } else {
+            let prefix = if !ends_with_newline { "\n" } else { "" };
+            let lines: Vec<String> =
+                keys_to_add.iter().map(|x| x.to_string()).collect();
+            format!("{}{}{}\n", kept, prefix, lines.join("\n")) // We want each to be on its own line while also appending a newline
+        };
+        let mut change = self.change(original, content, keys_to_add, removed);
+        change.conflicts = conflicts;
+
         // If we have no keys to write we can just exit
-        if keys_to_add.is_empty() || dry_run {
-            return Ok(keys_to_add.len());
+        if change.is_empty() || dry_run {
+            return Ok(change);
         }
 
-        let prefix = if !ends_with_newline {
-            String::from("\n")
+        self.replace(&change.after)?;
+        Ok(change)
+    }
+
+    /// Describes a change of the file contents
+    fn change(
+        &self,
+        before: String,
+        after: String,
+        added: Vec<AuthorizedKey>,
+        removed: Vec<AuthorizedKey>,
+    ) -> Change {
+        Change {
+            path: self.path.clone(),
+            before,
+            after,
+            added,
+            removed,
+            conflicts: vec![],
+            removals: vec![],
+        }
+    }
+}
+
+/// Where the keys of a user are written
+#[derive(Debug, Clone, PartialEq)]
+pub enum Target {
+    /// The authorized_keys file in the users home directory, as sshd is configured to read it
+    Home,
+    /// A root owned file per user in a central directory, which the user cannot edit
+    Central(PathBuf),
+    /// A root owned file per user in a cache directory, which sshd reads through keysync authorized-keys-command
+    Cache(PathBuf),
+    /// The TrustedUserCAKeys file of sshd, listing the CA keys trusted to sign user certificates
+    TrustedCa(PathBuf),
+}
+
+/// Where root keeps its lock files, so a user can not hold a lock keysync needs
+const LOCK_DIR: &str = "/run/keysync";
+
+/// How long to wait for a lock before giving up
+const LOCK_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// How long to wait between attempts to take a lock
+const LOCK_RETRY: std::time::Duration = std::time::Duration::from_millis(100);
+
+/// How many names are tried for the temporary file written before it replaces the authorized keys file
+const TEMP_ATTEMPTS: u32 = 100;
+
+/// The prefix of a cache target, as it is stored in the database
+const CACHE_PREFIX: &str = "cache:";
+
+/// The prefix of a TrustedUserCAKeys target, as it is stored in the database
+const TRUSTED_CA_PREFIX: &str = "trusted-ca:";
+
+/// Implements display for Target, as it is stored in the database
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Home => write!(f, "home"),
+            Target::Central(dir) => write!(f, "{}", dir.display()),
+            Target::Cache(dir) => {
+                write!(f, "{}{}", CACHE_PREFIX, dir.display())
+            }
+            Target::TrustedCa(file) => {
+                write!(f, "{}{}", TRUSTED_CA_PREFIX, file.display())
+            }
+        }
+    }
+}
+
+/// FromStr implementation, either `home`, the absolute path of a central directory, `cache:` and the absolute path of a cache directory, or `trusted-ca:` and the absolute path of the file
+impl FromStr for Target {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "home" {
+            return Ok(Target::Home);
+        }
+        if let Some(d) = s.strip_prefix(CACHE_PREFIX) {
+            let dir = PathBuf::from(d);
+            if !dir.is_absolute() {
+                return Err(anyhow!("Cache directory must be absolute: {}", d));
+            }
+            return Ok(Target::Cache(dir));
+        }
+        if let Some(f) = s.strip_prefix(TRUSTED_CA_PREFIX) {
+            let file = PathBuf::from(f);
+            if !file.is_absolute() {
+                return Err(anyhow!(
+                    "TrustedUserCAKeys file must be absolute: {}",
+                    f
+                ));
+            }
+            return Ok(Target::TrustedCa(file));
+        }
+        let dir = PathBuf::from(s);
+        if dir.is_absolute() {
+            Ok(Target::Central(dir))
         } else {
-            String::new()
-        };
-        let content: String = prefix + &keys_to_add.join("\n") + "\n"; // We want each to be on its own line while also appending a newline
+            Err(anyhow!("Central key directory must be absolute: {}", s))
+        }
+    }
+}
 
-        let mut file: File = fs::OpenOptions::new()
-            .write(true)
-            .append(true)
-            .open(&self.path)
-            .with_context(|| {
-                format!("Error opening keys file: {}", self.path.display())
-            })?;
+/// An exclusive advisory lock on an authorized keys file, unlocked when dropped
+pub struct FileLock {
+    _file: File,
+}
 
-        file.write_all(content.as_bytes()).with_context(|| {
-            format!("Error writing keys from file: {}", self.path.display())
-        })?;
-        Ok(keys_to_add.len())
+/// Opens the lock file, creating it when running as root, or None if it does not exist and can not be created
+/// Symbolic links are never followed, in case the lock directory was tampered with
+fn open_lock_file(path: &Path) -> Result<Option<File>> {
+    let mut options = fs::OpenOptions::new();
+    options.read(true).custom_flags(nix::libc::O_NOFOLLOW);
+    if Uid::effective().is_root() {
+        fs::DirBuilder::new()
+            .mode(0o755)
+            .recursive(true)
+            .create(LOCK_DIR)
+            .with_context(|| format!("Failed to create {}", LOCK_DIR))?;
+        options.write(true).create(true).mode(0o644);
+    }
+    match options.open(path) {
+        Ok(file) => Ok(Some(file)),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+        Err(e) => Err(Error::new(e)
+            .context(format!("Failed to open [{}]", path.display()))),
     }
 }
 
-/// Gets the User Id and Group Id of user provided, if no user was provided just returns the current user
-fn get_uid_gid<S: AsRef<str>>(user: Option<S>) -> Result<(Uid, Gid)> {
-    if let Some(u) = user {
-        match User::from_name(u.as_ref()).with_context(|| {
-            format!("Unable to get the Uid and Gid of user: {}", u.as_ref())
-        })? {
-            Some(user) => Ok((user.uid, user.gid)),
-            None => Ok((Uid::current(), Gid::current())),
+/// Takes an exclusive lock on the file, retrying until the timeout if it is already held
+fn lock_exclusive(
+    file: File,
+    name: &Path,
+    timeout: std::time::Duration,
+) -> Result<FileLock> {
+    let start = std::time::Instant::now();
+    loop {
+        match flock(file.as_raw_fd(), FlockArg::LockExclusiveNonblock) {
+            Ok(()) => return Ok(FileLock { _file: file }),
+            Err(Errno::EWOULDBLOCK) | Err(Errno::EINTR)
+                if start.elapsed() < timeout =>
+            {
+                std::thread::sleep(LOCK_RETRY)
+            }
+            Err(Errno::EWOULDBLOCK) => {
+                return Err(anyhow!(
+                    "Timed out waiting for the lock on [{}], another keysync process is holding it",
+                    name.display()
+                ))
+            }
+            Err(e) => {
+                return Err(Error::new(e)
+                    .context(format!("Failed to lock [{}]", name.display())))
+            }
         }
-    } else {
-        Ok((Uid::current(), Gid::current()))
     }
 }
 
+/// Writes the contents to the temporary file with the owner and mode of the original, and syncs it to disk
+fn write_synced(
+    file: &OpenFile,
+    content: &str,
+    original: &FileStat,
+) -> Result<()> {
+    file.write_all(content.as_bytes())
+        .context("Error writing keys to temporary file")?;
+    file.set_owner(
+        Uid::from_raw(original.st_uid),
+        Gid::from_raw(original.st_gid),
+        original.st_mode & 0o7777,
+    )
+    .context("Error setting ownership and mode of temporary file")?;
+    file.sync().context("Error syncing temporary file to disk")
+}
+
+/// Creates a new temporary file next to the file being replaced, trying another name when one is taken
+fn create_temp(dir: &Dir, name: &str) -> Result<(String, OpenFile)> {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.subsec_nanos())
+        .unwrap_or_default();
+    for attempt in 0..TEMP_ATTEMPTS {
+        let tmp_name = format!(
+            ".{}.{}.{}.keysync-tmp",
+            name,
+            std::process::id(),
+            nanos.wrapping_add(attempt)
+        );
+        match dir.create_file(&tmp_name, 0o600) {
+            Ok(file) => return Ok((tmp_name, file)),
+            Err(Errno::EEXIST) => continue,
+            Err(e) => {
+                return Err(Error::new(e).context(format!(
+                    "Error creating temporary file in: {}",
+                    dir.path().display()
+                )))
+            }
+        }
+    }
+    Err(anyhow!(
+        "Error creating temporary file in: {}, every name tried is taken",
+        dir.path().display()
+    ))
+}
+
+/// Creates an authorized keys file and any missing parent directory, with the expected owner and modes
+/// The directories below the home directory are created one at a time without following symbolic links
+fn create_file(path: &Path, expected: &Expected) -> Result<()> {
+    let (uid, gid) = (expected.uid, expected.gid);
+    let parent = match path.parent() {
+        Some(p) if !p.as_os_str().is_empty() => p,
+        _ => Path::new("."),
+    };
+    let mode = expected.dir_mode.unwrap_or(0o755);
+    let dir = Dir::create(parent, Some(&expected.home), ((uid, gid), mode))?;
+    let name = path
+        .file_name()
+        .map(|x| x.to_string_lossy().to_string())
+        .context("The authorized_keys path has no file name")?;
+    match dir.create_file(&name, expected.file_mode) {
+        Ok(file) => {
+            file.set_owner(uid, gid, expected.file_mode)
+                .with_context(|| {
+                    format!(
+                        "Failed to set authorized_keys [{}] ownership to user",
+                        path.display()
+                    )
+                })
+        }
+        // Created in the meantime, a symbolic link is refused when it is used
+        Err(Errno::EEXIST) => Ok(()),
+        Err(e) => Err(Error::new(e)
+            .context(format!("Failed to create [{}]", path.display()))),
+    }
+}
+
+/// Finds the imported keys that are already in the file with different options
+fn find_conflicts(
+    existing: &[AuthorizedKey],
+    imported: &[AuthorizedKey],
+) -> Vec<Conflict> {
+    imported
+        .iter()
+        .filter_map(|x| {
+            existing
+                .iter()
+                .find(|y| y.same_key(x))
+                .filter(|y| !keys::same_options(&y.options, &x.options))
+                .map(|y| Conflict {
+                    existing: y.clone(),
+                    imported: x.clone(),
+                })
+        })
+        .collect()
+}
+
+/// Removes the key lines the selector matches, given the index of the key (starting at 1) and the key
+/// Returns the remaining text with every other line kept as is, and the removed keys
+fn remove_lines<F: Fn(usize, &AuthorizedKey) -> bool>(
+    text: &str,
+    selector: F,
+) -> (String, Vec<AuthorizedKey>) {
+    let mut kept = String::new();
+    let mut removed: Vec<AuthorizedKey> = vec![];
+    let mut index = 0;
+    for line in text.split_inclusive('\n') {
+        if let Ok(key) = line.trim().parse::<AuthorizedKey>() {
+            index += 1;
+            if selector(index, &key) {
+                removed.push(key);
+                continue;
+            }
+        }
+        kept.push_str(line);
+    }
+    (kept, removed)
+}
+
+/// Gets the passwd entry of the user provided, if no user was provided returns the current user
+fn get_user<S: AsRef<str>>(user: Option<S>) -> Result<User> {
+    let entry = match &user {
+        Some(u) => User::from_name(u.as_ref()),
+        None => User::from_uid(Uid::current()),
+    }
+    .context("Unable to read the passwd database")?;
+    entry.with_context(|| match user {
+        Some(u) => format!("User {} does not exist", u.as_ref()),
+        None => String::from("The current user does not exist"),
+    })
+}
+
 /// Unit Tests
 #[cfg(test)]
 #[path = "./tests/file.rs"]
@@ -1,20 +1,75 @@
 use anyhow::{anyhow, Context, Result};
-use log::{debug, info};
+use chrono::{DateTime, NaiveDate, TimeZone, Utc};
+use log::{debug, info, warn};
 use reqwest::blocking::{Client, ClientBuilder, Response};
-use reqwest::Error;
+use reqwest::header::HeaderMap;
+use reqwest::{Error, StatusCode};
+use serde::Deserialize;
 use std::time::Duration;
-use std::vec;
+use std::{fmt, vec};
 use url::Url;
 
-use super::util;
+use super::keys::{self, AuthorizedKey};
+use super::provenance::Provenance;
 
 const GITHUB_URL: &str = "https://github.com/";
 const GITLAB_URL: &str = "https://gitlab.com/";
+const GITLAB_HOST: &str = "gitlab.com";
 const LAUNCHPAD_URL: &str = "https://launchpad.net/";
+const GITHUB_API_URL: &str = "https://api.github.com/";
+
+/// How many keys to ask GitLab for in each page, the most it allows
+const GITLAB_PER_PAGE: usize = 100;
+
+/// How many pages of GitLab keys to follow before giving up, far more keys than anyone has
+const MAX_GITLAB_PAGES: usize = 50;
+
+/// The provider answered that the account does not exist (404 or 410), it was deleted, suspended or renamed
+/// Any other failure, like a 5xx or a timeout, says nothing about the keys
+#[derive(Debug)]
+pub struct AccountGone {
+    pub url: String,
+}
+
+/// Implements display for AccountGone
+impl fmt::Display for AccountGone {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "The account of {} no longer exists", self.url)
+    }
+}
+
+impl std::error::Error for AccountGone {}
+
+/// Returns true if the error is because the account does not exist, rather than a transient failure
+pub fn is_gone(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<AccountGone>().is_some()
+}
+
+/// The provider refused to answer for now (403 or 429), usually because too many requests were made without a token
+#[derive(Debug)]
+pub struct RateLimited {
+    pub url: String,
+}
+
+/// Implements display for RateLimited
+impl fmt::Display for RateLimited {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Rate limited while requesting {}", self.url)
+    }
+}
+
+impl std::error::Error for RateLimited {}
+
+/// Returns true if the error is because the provider is rate limiting requests, which passes on its own
+pub fn is_rate_limited(error: &anyhow::Error) -> bool {
+    error.downcast_ref::<RateLimited>().is_some()
+}
 
 /// Network key request implementation
 pub struct Network {
     client: Client,
+    /// Hosts known to run GitLab, whose API is used for expiry dates and account IDs
+    gitlab_hosts: Vec<String>,
 }
 
 impl Network {
@@ -22,18 +77,47 @@ impl Network {
     pub fn new() -> Self {
         let timeout = Duration::new(10, 0);
         let network = Network {
-            client: ClientBuilder::new().timeout(timeout).build().unwrap(),
+            // The GitHub API refuses requests without a user agent
+            client: ClientBuilder::new()
+                .timeout(timeout)
+                .user_agent(concat!("keysync/", env!("CARGO_PKG_VERSION")))
+                .build()
+                .unwrap(),
+            gitlab_hosts: vec![GITLAB_HOST.to_owned()],
         };
         info!("Created Network object");
         network
     }
 
-    /// Gets the SSH keys from a requested url (as string)
-    /// Return a Vector of Strings that have been cleaned
+    /// Also trusts the hosts to run GitLab, any .keys url looks like GitLab so only known hosts get API requests
+    pub fn with_gitlab_hosts(mut self, hosts: &[String]) -> Self {
+        self.gitlab_hosts.extend(hosts.iter().cloned());
+        self
+    }
+
+    /// Gets the SSH keys from a requested url (as string), optionally imported by a job
+    /// Return a Vector of parsed keys tagged with where they came from, any options provided by the remote are discarded
+    /// GitLab keys are retrieved from the API instead when possible, to learn when they expire
     pub fn get_keys<S: AsRef<str>>(
         &self,
         request_url: S,
-    ) -> Result<Vec<String>> {
+        job: Option<u32>,
+    ) -> Result<Vec<AuthorizedKey>> {
+        let provenance =
+            Provenance::new(request_url.as_ref(), job, &self.gitlab_hosts);
+        if provenance.provider == "gitlab" {
+            if let Some(api_url) = gitlab_api_url(request_url.as_ref()) {
+                match self.get_gitlab_keys(&api_url) {
+                    Ok(keys) => return Ok(provenance.tag(keys)),
+                    Err(e) => warn!(
+                        "{}, falling back to {} without expiry dates",
+                        e,
+                        request_url.as_ref()
+                    ),
+                }
+            }
+        }
+
         let response: Result<Response, Error> = self
             .client
             .get(request_url.as_ref())
@@ -46,42 +130,96 @@ impl Network {
         match response {
             Ok(resp) => {
                 let text = resp.text()?;
-                let keys = util::clean_keys(util::split_keys(&text));
+                let keys: Vec<AuthorizedKey> = keys::parse(&text)
+                    .into_iter()
+                    .map(|mut x| {
+                        x.options.clear();
+                        x
+                    })
+                    .collect();
                 debug!(
                     "Retrieved {} keys from {}",
                     keys.len(),
                     request_url.as_ref()
                 );
-                Ok(keys)
+                Ok(provenance.tag(keys))
+            }
+            Err(e) => Err(status_error(request_url.as_ref(), e)),
+        }
+    }
+
+    /// Gets the keys of a GitLab user from the keys API, with their expiry dates as expiry-time options
+    /// The API is paginated, so every page is followed until GitLab says there is no next one
+    fn get_gitlab_keys(&self, api_url: &str) -> Result<Vec<AuthorizedKey>> {
+        let mut keys: Vec<AuthorizedKey> = vec![];
+        let mut page = String::from("1");
+        for _ in 0..MAX_GITLAB_PAGES {
+            let url = format!("{}&page={}", api_url, page);
+            let response = self
+                .client
+                .get(&url)
+                .send()
+                .and_then(|x| x.error_for_status())
+                .with_context(|| format!("Error getting keys from: {}", url))?;
+            let next = next_page(response.headers());
+            let text = response
+                .text()
+                .with_context(|| format!("Error getting keys from: {}", url))?;
+            keys.extend(parse_gitlab_keys(&text).with_context(|| {
+                format!("Error reading keys from: {}", url)
+            })?);
+            match next {
+                Some(n) => page = n,
+                None => {
+                    debug!("Retrieved {} keys from {}", keys.len(), api_url);
+                    return Ok(keys);
+                }
             }
-            Err(e) => Err(anyhow!("{}", e)),
         }
+        Err(anyhow!("Too many pages of keys from: {}", api_url))
+    }
+
+    /// Gets the numeric ID of the GitHub or GitLab account a keys url belongs to, which stays the same when it is renamed
+    /// Returns None for other providers, which have no such ID, and for GitLab hosts that are not known
+    pub fn account_id(&self, request_url: &str) -> Result<Option<String>> {
+        let api_url = match account_api_url(request_url, &self.gitlab_hosts) {
+            Some(u) => u,
+            None => return Ok(None),
+        };
+        let text = self
+            .client
+            .get(&api_url)
+            .send()
+            .with_context(|| {
+                format!("Error getting the account from: {}", api_url)
+            })?
+            .error_for_status()
+            .map_err(|e| status_error(request_url, e))?
+            .text()?;
+        let id = parse_account_id(&text)
+            .with_context(|| {
+                format!("Error reading the account from: {}", api_url)
+            })?
+            .ok_or_else(|| AccountGone {
+                url: request_url.to_owned(),
+            })?;
+        debug!("Account of {} has ID {}", request_url, id);
+        Ok(Some(id))
     }
 
-    /// Gets all the keys from the provided services, if none are selected it will still grab from Github
-    pub fn get_keys_services<S: AsRef<str>>(
+    /// Gets all the keys from the provided urls, without duplicates
+    pub fn get_keys_urls<S: AsRef<str>>(
         &self,
-        username: S,
-        github: bool,
-        launchpad: bool,
-        gitlab: bool,
-        gitlab_url: Option<Url>,
-    ) -> Result<Vec<String>> {
-        let mut all_keys: Vec<String> = vec![];
-        let urls: Vec<String> = create_urls(
-            username.as_ref(),
-            github,
-            launchpad,
-            gitlab,
-            gitlab_url,
-        );
+        urls: &[S],
+    ) -> Result<Vec<AuthorizedKey>> {
+        let mut all_keys: Vec<AuthorizedKey> = vec![];
         for url in urls {
-            let mut keys = self.get_keys(url)?;
+            let mut keys = self.get_keys(url, None)?;
             all_keys.append(&mut keys);
         }
 
-        all_keys.sort();
-        all_keys.dedup(); // Dedup ineffective without sorted keys
+        all_keys.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+        all_keys.dedup_by(|a, b| a.same_key(b)); // Dedup ineffective without sorted keys
         info!("Retrieved {} unique keys", all_keys.len());
         Ok(all_keys)
     }
@@ -116,6 +254,130 @@ pub fn create_urls(
     urls
 }
 
+/// A key as returned by the GitLab keys API
+#[derive(Debug, Deserialize)]
+struct GitlabKey {
+    key: String,
+    created_at: Option<String>,
+    expires_at: Option<String>,
+    /// Either auth, signing or auth_and_signing, older GitLab versions only have auth keys
+    usage_type: Option<String>,
+}
+
+/// An account as returned by the GitHub and GitLab users APIs
+#[derive(Debug, Deserialize)]
+struct Account {
+    id: u64,
+}
+
+/// GitHub returns the account, GitLab a list of the accounts with the username
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum Accounts {
+    One(Account),
+    Many(Vec<Account>),
+}
+
+/// Gets the users API url to look up the account of a GitHub or GitLab .keys url, on a known GitLab host
+fn account_api_url(url: &str, gitlab_hosts: &[String]) -> Option<String> {
+    let provenance = Provenance::new(url, None, gitlab_hosts);
+    match provenance.provider.as_str() {
+        "github" => {
+            Some(format!("{}users/{}", GITHUB_API_URL, provenance.user))
+        }
+        "gitlab" => {
+            let (base, username) =
+                url.strip_suffix(".keys")?.rsplit_once('/')?;
+            Some(format!("{}/api/v4/users?username={}", base, username))
+        }
+        _ => None,
+    }
+}
+
+/// Parses the ID of the account from a users API response, None if there is no account with the username
+fn parse_account_id(json: &str) -> Result<Option<String>> {
+    let account = match serde_json::from_str(json)? {
+        Accounts::One(a) => Some(a),
+        Accounts::Many(a) => a.into_iter().next(),
+    };
+    Ok(account.map(|x| x.id.to_string()))
+}
+
+/// Converts the error of a failed response, a missing account becomes AccountGone and rate limiting RateLimited
+fn status_error(url: &str, error: Error) -> anyhow::Error {
+    match error.status() {
+        Some(StatusCode::NOT_FOUND | StatusCode::GONE) => AccountGone {
+            url: url.to_owned(),
+        }
+        .into(),
+        Some(StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS) => {
+            RateLimited {
+                url: url.to_owned(),
+            }
+            .into()
+        }
+        _ => anyhow!("{}", error),
+    }
+}
+
+/// Gets the GitLab keys API url for a .keys url, keeping any path GitLab is hosted under
+fn gitlab_api_url(url: &str) -> Option<String> {
+    let (base, username) = url.strip_suffix(".keys")?.rsplit_once('/')?;
+    Some(format!(
+        "{}/api/v4/users/{}/keys?per_page={}",
+        base, username, GITLAB_PER_PAGE
+    ))
+}
+
+/// Gets the next page of a paginated GitLab response, None on the last page
+fn next_page(headers: &HeaderMap) -> Option<String> {
+    headers
+        .get("x-next-page")?
+        .to_str()
+        .ok()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty())
+        .map(|x| x.to_owned())
+}
+
+/// Parses the response of the GitLab keys API, skipping keys that are only for signing
+/// Expiry dates are kept as an expiry-time option, and creation dates for the quarantine of new keys
+fn parse_gitlab_keys(json: &str) -> Result<Vec<AuthorizedKey>> {
+    let response: Vec<GitlabKey> = serde_json::from_str(json)?;
+    let mut keys: Vec<AuthorizedKey> = vec![];
+    for entry in response {
+        if entry.usage_type.as_deref() == Some("signing") {
+            continue;
+        }
+        let mut key: AuthorizedKey = match entry.key.parse() {
+            Ok(k) => k,
+            Err(e) => {
+                debug!("Skipping key: {}", e);
+                continue;
+            }
+        };
+        key.options.clear();
+        if let Some(created) = entry.created_at.as_deref() {
+            key.created = Some(parse_gitlab_date(created)?);
+        }
+        if let Some(expires) = entry.expires_at.as_deref() {
+            key.set_expiry(parse_gitlab_date(expires)?);
+        }
+        keys.push(key);
+    }
+    Ok(keys)
+}
+
+/// Parses a GitLab expiry, either a timestamp or a date which expires at its start in UTC
+fn parse_gitlab_date(date: &str) -> Result<DateTime<Utc>> {
+    if let Ok(d) = DateTime::parse_from_rfc3339(date) {
+        return Ok(d.with_timezone(&Utc));
+    }
+    let day = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .with_context(|| format!("Invalid expiry date: {}", date))?;
+    Ok(Utc.from_utc_datetime(&day.and_hms_opt(0, 0, 0).unwrap()))
+}
+
 /// Creates a GitHub keys url with a username
 fn get_github(username: &str) -> String {
     let url = format!("{}{}.keys", GITHUB_URL, username);
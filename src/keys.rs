@@ -0,0 +1,415 @@
+use anyhow::{anyhow, Context, Error, Result};
+use chrono::{DateTime, Local, NaiveDateTime, TimeZone, Utc};
+use log::debug;
+use std::fmt;
+use std::str::FromStr;
+
+use super::sshkey::{self, KeyData};
+
+/// Key types supported in an authorized_keys file, see sshd(8)
+const KEY_TYPES: &[&str] = &[
+    "ssh-rsa",
+    "ecdsa-sha2-nistp256",
+    "ecdsa-sha2-nistp384",
+    "ecdsa-sha2-nistp521",
+    "ssh-ed25519",
+    "ssh-dss",
+    // Security keys (FIDO/U2F)
+    "sk-ecdsa-sha2-nistp256@openssh.com",
+    "sk-ssh-ed25519@openssh.com",
+    "ssh-xmss@openssh.com",
+    // Certificates
+    "ssh-rsa-cert-v01@openssh.com",
+    "ssh-dss-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp256-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp384-cert-v01@openssh.com",
+    "ecdsa-sha2-nistp521-cert-v01@openssh.com",
+    "ssh-ed25519-cert-v01@openssh.com",
+    "sk-ecdsa-sha2-nistp256-cert-v01@openssh.com",
+    "sk-ssh-ed25519-cert-v01@openssh.com",
+    "ssh-xmss-cert-v01@openssh.com",
+];
+
+/// Options supported by sshd in an authorized_keys file, along with whether they require a value
+const OPTIONS: &[(&str, bool)] = &[
+    ("agent-forwarding", false),
+    ("cert-authority", false),
+    ("command", true),
+    ("environment", true),
+    ("expiry-time", true),
+    ("from", true),
+    ("no-agent-forwarding", false),
+    ("no-port-forwarding", false),
+    ("no-pty", false),
+    ("no-touch-required", false),
+    ("no-user-rc", false),
+    ("no-x11-forwarding", false),
+    ("permitlisten", true),
+    ("permitopen", true),
+    ("port-forwarding", false),
+    ("principals", true),
+    ("pty", false),
+    ("restrict", false),
+    ("tunnel", true),
+    ("user-rc", false),
+    ("verify-required", false),
+    ("x11-forwarding", false),
+];
+
+/// Options sshd accepts more than once, every other option may only be given once
+const REPEATABLE_OPTIONS: &[&str] =
+    &["environment", "permitlisten", "permitopen"];
+
+/// A single option of an authorized_keys entry, Ex: `no-pty` or `from="10.0.0.0/8"`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyOption {
+    pub name: String,
+    pub value: Option<String>,
+}
+
+/// Implements display for KeyOption, quoting and escaping the value if there is one
+impl fmt::Display for KeyOption {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.value {
+            Some(v) => {
+                write!(f, "{}=\"{}\"", self.name, v.replace('"', "\\\""))
+            }
+            None => write!(f, "{}", self.name),
+        }
+    }
+}
+
+/// A single key entry of an authorized_keys file, see the AUTHORIZED_KEYS section of sshd(8)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthorizedKey {
+    pub options: Vec<KeyOption>,
+    pub key_type: String,
+    pub blob: String,
+    pub comment: Option<String>,
+    /// Size of the key in bits, decoded from the blob
+    pub bits: u32,
+    /// SHA256 fingerprint, decoded from the blob
+    pub fingerprint: String,
+    /// When the key was added at the provider, if it says, this is not written to the file
+    pub created: Option<DateTime<Utc>>,
+}
+
+/// Implements display for AuthorizedKey, formatted as a line of an authorized_keys file
+impl fmt::Display for AuthorizedKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if !self.options.is_empty() {
+            write!(f, "{} ", format_options(&self.options))?;
+        }
+        write!(f, "{} {}", self.key_type, self.blob)?;
+        if let Some(c) = &self.comment {
+            write!(f, " {}", c)?;
+        }
+        Ok(())
+    }
+}
+
+/// FromStr implementation, parses a single line of an authorized_keys file
+impl FromStr for AuthorizedKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let line = s.trim();
+        if line.is_empty() || line.starts_with('#') {
+            return Err(anyhow!("Line does not contain a key"));
+        }
+
+        // Options are only present if the line does not start with a key type
+        let (options, rest) = if is_key_type(first_word(line)) {
+            (vec![], line)
+        } else {
+            let (options, rest) = split_options(line)?;
+            (parse_options(options)?, rest.trim_start())
+        };
+
+        let key_type = first_word(rest);
+        if !is_key_type(key_type) {
+            return Err(anyhow!("Unknown key type: {}", key_type));
+        }
+        let rest = rest[key_type.len()..].trim_start();
+        let blob = first_word(rest);
+        let data: KeyData =
+            sshkey::decode(key_type, blob).with_context(|| {
+                format!("Invalid key data for type: {}", key_type)
+            })?;
+        let comment = rest[blob.len()..].trim();
+
+        Ok(AuthorizedKey {
+            options,
+            key_type: key_type.to_owned(),
+            blob: blob.to_owned(),
+            comment: if comment.is_empty() {
+                None
+            } else {
+                Some(comment.to_owned())
+            },
+            bits: data.bits,
+            fingerprint: data.fingerprint,
+            created: None,
+        })
+    }
+}
+
+impl AuthorizedKey {
+    /// Returns true if both entries hold the same key by fingerprint, regardless of options or comment
+    pub fn same_key(&self, other: &AuthorizedKey) -> bool {
+        self.fingerprint == other.fingerprint
+    }
+
+    /// Gets when sshd stops accepting the key, from its expiry-time option
+    pub fn expires(&self) -> Option<DateTime<Utc>> {
+        self.options
+            .iter()
+            .find(|x| x.name.eq_ignore_ascii_case("expiry-time"))
+            .and_then(|x| x.value.as_deref())
+            .and_then(parse_timespec)
+    }
+
+    /// Sets the expiry-time option of the key, replacing any it had
+    pub fn set_expiry(&mut self, at: DateTime<Utc>) {
+        self.options
+            .retain(|x| !x.name.eq_ignore_ascii_case("expiry-time"));
+        self.options.push(expiry_time(at.with_timezone(&Local)));
+    }
+
+    /// Returns true if the key has an expiry-time that has passed
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool {
+        self.expires().is_some_and(|x| x <= now)
+    }
+}
+
+/// Parses the contents of an authorized_keys file (or a provider response), skipping blank lines, comments and invalid lines
+pub fn parse(text: &str) -> Vec<AuthorizedKey> {
+    text.lines()
+        .map(|x| x.trim())
+        .filter(|x| !x.is_empty() && !x.starts_with('#'))
+        .filter_map(|x| match x.parse::<AuthorizedKey>() {
+            Ok(k) => Some(k),
+            Err(e) => {
+                debug!("Skipping line: {}", e);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Parses a comma separated option list, Ex: `from="10.0.0.0/8",no-pty`
+pub fn parse_options(options: &str) -> Result<Vec<KeyOption>> {
+    let mut parsed: Vec<KeyOption> = vec![];
+    let mut chars = options.chars().peekable();
+
+    loop {
+        let mut name = String::new();
+        while let Some(c) = chars.next_if(|x| *x != '=' && *x != ',') {
+            if c.is_whitespace() || c == '"' {
+                return Err(anyhow!("Invalid option name in: {}", options));
+            }
+            name.push(c);
+        }
+        if name.is_empty() {
+            return Err(anyhow!("Empty option in: {}", options));
+        }
+
+        let mut value: Option<String> = None;
+        if chars.next_if_eq(&'=').is_some() {
+            if chars.next() != Some('"') {
+                return Err(anyhow!(
+                    "Option value for {} must be quoted",
+                    name
+                ));
+            }
+            let mut v = String::new();
+            loop {
+                match chars.next() {
+                    Some('\\') if chars.peek() == Some(&'"') => {
+                        v.push('"');
+                        chars.next();
+                    }
+                    Some('"') => break,
+                    Some(c) => v.push(c),
+                    None => {
+                        return Err(anyhow!(
+                            "Missing end quote for option: {}",
+                            name
+                        ))
+                    }
+                }
+            }
+            value = Some(v);
+        }
+        parsed.push(KeyOption { name, value });
+
+        match chars.next() {
+            Some(',') => continue,
+            None => break,
+            Some(c) => {
+                return Err(anyhow!("Unexpected character '{}' in options", c))
+            }
+        }
+    }
+    Ok(parsed)
+}
+
+/// Verifies the options are ones sshd understands, with a value only where sshd expects one
+/// Only options sshd allows more than once may be repeated
+pub fn validate_options(options: &[KeyOption]) -> Result<()> {
+    for (i, option) in options.iter().enumerate() {
+        let name = option.name.to_lowercase();
+        let repeated = options[..i]
+            .iter()
+            .any(|x| x.name.eq_ignore_ascii_case(&name));
+        if repeated && !REPEATABLE_OPTIONS.contains(&name.as_str()) {
+            return Err(anyhow!("Option {} is given more than once", name));
+        }
+        let takes_value = match OPTIONS.iter().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v,
+            None => return Err(anyhow!("Unknown option: {}", option.name)),
+        };
+
+        let value = match (&option.value, takes_value) {
+            (Some(v), true) => v,
+            (None, false) => continue,
+            (Some(_), false) => {
+                return Err(anyhow!("Option {} does not take a value", name))
+            }
+            (None, true) => {
+                return Err(anyhow!("Option {} requires a value", name))
+            }
+        };
+
+        let valid = match name.as_str() {
+            "environment" => value
+                .split_once('=')
+                .map(|(n, _)| !n.is_empty())
+                .unwrap_or(false),
+            "expiry-time" => is_timespec(value),
+            "permitopen" => value.rsplit_once(':').is_some(),
+            "tunnel" => value.parse::<u32>().is_ok(),
+            _ => !value.is_empty(),
+        };
+        if !valid {
+            return Err(anyhow!(
+                "Invalid value for option {}: {}",
+                name,
+                value
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Formats a list of options as they would appear in an authorized_keys file
+pub fn format_options(options: &[KeyOption]) -> String {
+    options
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<String>>()
+        .join(",")
+}
+
+/// Returns true if both lists have the same options, in any order and regardless of the case of the names
+pub fn same_options(a: &[KeyOption], b: &[KeyOption]) -> bool {
+    let normalize = |options: &[KeyOption]| {
+        let mut names: Vec<String> = options
+            .iter()
+            .map(|x| {
+                KeyOption {
+                    name: x.name.to_lowercase(),
+                    value: x.value.clone(),
+                }
+                .to_string()
+            })
+            .collect();
+        names.sort();
+        names
+    };
+    normalize(a) == normalize(b)
+}
+
+/// Splits a line into its options and the remainder, options end at the first whitespace not inside quotes
+fn split_options(line: &str) -> Result<(&str, &str)> {
+    let mut quoted = false;
+    let mut escaped = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '\\' if quoted && !escaped => {
+                escaped = true;
+                continue;
+            }
+            '"' if !escaped => quoted = !quoted,
+            c if c.is_whitespace() && !quoted => {
+                return Ok((&line[..i], &line[i..]))
+            }
+            _ => (),
+        }
+        escaped = false;
+    }
+    Err(anyhow!("Line contains options but no key"))
+}
+
+/// Sets the options of an imported key, keeping the expiry set by the provider if it is before the one in the options
+pub fn apply_options(key: &mut AuthorizedKey, options: &[KeyOption]) {
+    let provider_expiry = key.expires();
+    key.options = options.to_vec();
+    if let Some(at) = provider_expiry {
+        if key.expires().is_none_or(|x| at < x) {
+            key.set_expiry(at);
+        }
+    }
+}
+
+/// Creates an expiry-time option for the time, written in local time like sshd reads it
+pub fn expiry_time(at: DateTime<Local>) -> KeyOption {
+    KeyOption {
+        name: String::from("expiry-time"),
+        value: Some(at.format("%Y%m%d%H%M").to_string()),
+    }
+}
+
+/// Parses an expiry-time timespec, which is in local time unless it ends with Z
+pub fn parse_timespec(value: &str) -> Option<DateTime<Utc>> {
+    if !is_timespec(value) {
+        return None;
+    }
+    let digits = value.trim_end_matches(['Z', 'z']);
+    let padded = format!("{:0<14}", digits); // Missing hours, minutes and seconds are midnight
+    let time = NaiveDateTime::parse_from_str(&padded, "%Y%m%d%H%M%S").ok()?;
+    if digits.len() < value.len() {
+        Some(Utc.from_utc_datetime(&time))
+    } else {
+        Some(
+            Local
+                .from_local_datetime(&time)
+                .earliest()?
+                .with_timezone(&Utc),
+        )
+    }
+}
+
+/// Returns true if the value is a valid expiry-time timespec, YYYYMMDD[HHMM[SS]] with an optional trailing Z
+fn is_timespec(value: &str) -> bool {
+    let digits = value
+        .strip_suffix(|c| c == 'Z' || c == 'z')
+        .unwrap_or(value);
+    matches!(digits.len(), 8 | 12 | 14)
+        && digits.chars().all(|c| c.is_ascii_digit())
+}
+
+/// Returns the first whitespace separated word of a string
+fn first_word(s: &str) -> &str {
+    s.split_whitespace().next().unwrap_or("")
+}
+
+/// Returns true if the key type is one we support
+fn is_key_type(key_type: &str) -> bool {
+    KEY_TYPES.contains(&key_type)
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/keys.rs"]
+mod test;
@@ -1,26 +1,46 @@
+mod backup;
+mod change;
 mod cli;
+mod config;
 mod daemon;
 mod db;
+mod dir;
 mod file;
 mod http;
+mod keys;
+mod mapping;
+mod perms;
+mod policy;
+mod provenance;
 mod service;
+mod sshd;
+mod sshkey;
 mod util;
 
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, Context, Result};
+use chrono::{Local, Utc};
 use clap::ArgMatches;
 use cli::DefaultCron;
 use cron::Schedule;
-use log::info;
+use log::{info, warn};
 use nix::unistd::{Uid, User};
 use url::Url;
 
+use config::Config;
 use daemon::Daemon;
 use db::Database;
-use file::AuthorizedKeys;
+use file::{AuthorizedKeys, Target};
 use http::Network;
+use keys::{AuthorizedKey, KeyOption};
+use policy::Policy;
+use provenance::{Provenance, Source};
+use serde::Serialize;
+use sshd::SshdConfig;
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::ErrorKind;
 
 #[forbid(unsafe_code)]
-
 /// Main, returns () on success
 fn main() -> Result<()> {
     let matches = cli::app().get_matches();
@@ -52,6 +72,14 @@ fn main() -> Result<()> {
         Some(("set", m)) => set(m)?,
         Some(("jobs", m)) => jobs(m)?,
         Some(("remove", m)) => remove(m)?,
+        Some(("keys", m)) => list_keys(m)?,
+        Some(("remove-key", m)) => remove_key(m)?,
+        Some(("tidy", m)) => tidy(m)?,
+        Some(("backups", m)) => backups(m)?,
+        Some(("rollback", m)) => rollback(m)?,
+        Some(("doctor", m)) => doctor(m)?,
+        Some(("apply", m)) => apply(m)?,
+        Some(("authorized-keys-command", m)) => authorized_keys_command(m)?,
         Some(("daemon", m)) => daemon(m)?,
         _ => unreachable!(),
     }
@@ -63,43 +91,76 @@ fn main() -> Result<()> {
 fn get(m: &ArgMatches) -> Result<()> {
     let username: String = m.value_of_t("username")?;
 
-    let user: Option<String> = if m.is_present("user") {
-        Some(m.value_of_t("user")?)
-    } else {
-        None
+    // No users means the current one
+    let users: Vec<Option<String>> = match get_users(m)? {
+        u if u.is_empty() => vec![None],
+        u => u.into_iter().map(Some).collect(),
     };
-    exit_if_root(user.as_ref())?;
+    for user in users.iter() {
+        exit_if_root(user.as_ref())?;
+    }
 
     info!("Getting data for {}", username);
 
-    let mut gitlab_url: Option<Url> = None;
-    let gitlab: bool = if let Some(u) = m.value_of("gitlab") {
-        if !u.is_empty() {
-            gitlab_url = Some(Url::parse(u)?);
-        }
-        true
-    } else {
-        false
-    };
-
-    let network: Network = Network::new();
-
-    let keys: Vec<String> = network.get_keys_services(
-        username,
-        m.is_present("github"),
-        m.is_present("launchpad"),
-        gitlab,
-        gitlab_url,
-    )?;
+    let urls: Vec<String> = get_urls(m)?;
+    let options: Vec<KeyOption> = get_options(m, m.value_of("ttl"))?;
+    let policy: Policy = get_policy(m)?;
+    let config: Config = Config::load()?;
 
-    let authorized_keys: AuthorizedKeys = AuthorizedKeys::open(user)?;
+    // A GitLab url given on the command line is known to run GitLab
+    let mut gitlab_hosts: Vec<String> = config.gitlab_hosts.clone();
+    if let Some(u) = m.value_of("gitlab").filter(|x| !x.is_empty()) {
+        gitlab_hosts.extend(Url::parse(u)?.host_str().map(|x| x.to_owned()));
+    }
+    let network: Network = Network::new().with_gitlab_hosts(&gitlab_hosts);
 
     let dry_run = m.is_present("dry_run");
-    let count = authorized_keys.write_keys(keys, dry_run)?;
+    for url in urls.iter() {
+        daemon::verify_account(url, &network, dry_run)?;
+    }
+    let keys: Vec<AuthorizedKey> = network.get_keys_urls(&urls)?;
+    let (keys, rejected) = policy::enforce(keys, &[&config.policy, &policy]);
+    for rejection in rejected.iter() {
+        println!("{}", rejection);
+    }
+    let keys: Vec<AuthorizedKey> = hold_back(keys, &config)?;
+
+    let target: Target = get_target(m, &config)?;
+    let many = users.len() > 1;
+    let mut added: usize = 0;
+    for user in users {
+        if let Some(u) = user.as_ref().filter(|_| many) {
+            println!("{}:", u);
+        }
+        let authorized_keys: AuthorizedKeys = if dry_run {
+            AuthorizedKeys::find(user, &target, &config)?
+        } else {
+            AuthorizedKeys::open(user, &target, &config)?
+        };
+        let change =
+            authorized_keys.write_keys(keys.clone(), &options, dry_run)?;
+        if dry_run {
+            print!("{}", change.diff());
+        } else {
+            for key in change.added.iter() {
+                println!("{} {} {}", key.bits, key.fingerprint, key.key_type);
+            }
+            for key in change.removed.iter() {
+                println!(
+                    "Removed expired {} {}",
+                    key.fingerprint, key.key_type
+                );
+            }
+            for conflict in change.conflicts.iter() {
+                println!("{}", conflict);
+            }
+        }
+        added += change.added.len();
+    }
     println!(
         "{} {} new keys",
         if dry_run { "Found" } else { "Added" },
-        count
+        added
     );
 
     Ok(())
@@ -107,16 +168,19 @@ fn get(m: &ArgMatches) -> Result<()> {
 
 /// Adds a new schedule for the Systemd service to run
 fn set(m: &ArgMatches) -> Result<()> {
-    // Get variables
-    let user: String = if m.is_present("user") {
-        m.value_of_t("user")?
-    } else {
-        util::get_current_user()?
+    // Get variables, a group is stored as is so the daemon follows its members
+    let members: Vec<String> = match get_users(m)? {
+        u if u.is_empty() => vec![util::get_current_user()?],
+        u => u,
+    };
+    for member in members.iter() {
+        exit_if_root(Some(member))?;
+    }
+    let users: Vec<String> = match m.value_of("group") {
+        Some(g) => vec![format!("{}{}", db::GROUP_PREFIX, g)],
+        None => members.clone(),
     };
 
-    exit_if_root(Some(&user))?;
-
-    let username: String = m.value_of_t("username")?;
     let cron: Schedule = if m.is_present("cron") {
         m.value_of_t("cron")?
     } else {
@@ -124,42 +188,50 @@ fn set(m: &ArgMatches) -> Result<()> {
         default_cron.to_schedule()
     };
 
-    let mut gitlab_url: Option<Url> = None;
-    let gitlab: bool = if let Some(u) = m.value_of("gitlab") {
-        if !u.is_empty() {
-            gitlab_url = Some(Url::parse(u)?);
-        }
-        true
-    } else {
-        false
-    };
+    let urls: Vec<String> = get_urls(m)?;
+    let options: String = keys::format_options(&get_options(m, None)?);
+    let policy: String = get_policy(m)?.to_string();
 
-    util::run_as_root(Some(&user))?;
+    // Users given in the arguments are kept when rerun with sudo
+    if m.is_present("user") || m.is_present("group") {
+        util::run_as_root(None)?;
+    } else {
+        util::run_as_root(Some(&members[0]))?;
+    }
 
     if !m.is_present("skip_check") {
         service::check()?
     };
 
-    AuthorizedKeys::open(Some(&user))?;
-
-    let urls: Vec<String> = http::create_urls(
-        &username,
-        m.is_present("github"),
-        m.is_present("launchpad"),
-        gitlab,
-        gitlab_url,
-    );
+    let config: Config = Config::load()?;
+    let target: Target = get_target(m, &config)?;
+    for member in members.iter() {
+        if m.is_present("dry_run") {
+            AuthorizedKeys::find(Some(member), &target, &config)?;
+        } else {
+            AuthorizedKeys::open(Some(member), &target, &config)?;
+        }
+    }
 
     if !m.is_present("dry_run") {
         let database = Database::open()?;
-        for url in urls {
-            if database.add_schedule(&user, &cron.to_string(), &url)? {
-                println!(
-                    "Successfully added import schedule with url: {}",
-                    url
-                );
-            } else {
-                return Err(anyhow!("Job already exists"));
+        for user in users.iter() {
+            for url in urls.iter() {
+                if database.add_schedule(
+                    user,
+                    &cron.to_string(),
+                    url,
+                    &options,
+                    &target.to_string(),
+                    &policy,
+                )? {
+                    println!(
+                        "Successfully added import schedule for {} with url: {}",
+                        user, url
+                    );
+                } else {
+                    return Err(anyhow!("Job already exists for {}", user));
+                }
             }
         }
     } else {
@@ -188,18 +260,60 @@ fn jobs(m: &ArgMatches) -> Result<()> {
         if total_jobs == 1 { "" } else { "s" }
     );
     if total_jobs > 0 {
-        println!("{:<5}{:<15}{:<25}{:<45}", "ID", "User", "Cron", "Url");
-        println!("{:-<90}", "");
+        println!(
+            "{:<5}{:<15}{:<25}{:<45}{:<20}{:<30}{:<12}{:<10}",
+            "ID",
+            "User",
+            "Cron",
+            "Url",
+            "Target",
+            "Policy",
+            "Account",
+            "Options"
+        );
+        println!("{:-<162}", "");
         for job in jobs {
             println!(
-                "{:<5}{:<15}{:<25}{:<40}",
+                "{:<5}{:<15}{:<25}{:<45}{:<20}{:<30}{:<12}{}",
                 job.id.unwrap_or(0),
                 job.user,
                 job.cron,
-                job.url
+                job.url,
+                job.target,
+                job.policy,
+                database.pinned_account(&job.url)?.unwrap_or_default(),
+                job.options
             );
         }
     }
+
+    // Keys that jobs hold back until they have been served for the quarantine period
+    if let Some(period) = Config::load()?.quarantine()? {
+        let now = Utc::now();
+        let pending: Vec<db::Pending> = database
+            .get_pending()?
+            .into_iter()
+            .filter(|x| x.since + period > now)
+            .collect();
+        if !pending.is_empty() {
+            println!("\nFound {} pending key(s).\n", pending.len());
+            println!(
+                "{:<5}{:<52}{:<25}",
+                "Job", "Fingerprint", "Pending until"
+            );
+            println!("{:-<82}", "");
+            for key in pending {
+                println!(
+                    "{:<5}{:<52}{}",
+                    key.job,
+                    key.fingerprint,
+                    (key.since + period)
+                        .with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M")
+                );
+            }
+        }
+    }
     Ok(())
 }
 
@@ -227,6 +341,424 @@ fn remove(m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// A key of an authorized_keys file as it is listed
+#[derive(Serialize)]
+struct KeyEntry {
+    index: usize,
+    key_type: String,
+    bits: u32,
+    fingerprint: String,
+    options: String,
+    comment: Option<String>,
+    managed: bool,
+    provenance: Option<Provenance>,
+}
+
+/// Lists the keys of an authorized_keys file
+fn list_keys(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+
+    let config: Config = Config::load()?;
+    let authorized_keys =
+        AuthorizedKeys::find(user, &get_target(m, &config)?, &config)?;
+    let entries: Vec<KeyEntry> = authorized_keys
+        .keys()?
+        .into_iter()
+        .enumerate()
+        .map(|(i, x)| {
+            let provenance =
+                x.comment.as_deref().and_then(Provenance::from_comment);
+            KeyEntry {
+                index: i + 1,
+                managed: provenance::is_managed(&x),
+                // Show the original comment of managed keys
+                comment: match &provenance {
+                    Some(p) => p.comment.clone(),
+                    None => x.comment.clone(),
+                },
+                options: keys::format_options(&x.options),
+                key_type: x.key_type,
+                bits: x.bits,
+                fingerprint: x.fingerprint,
+                provenance,
+            }
+        })
+        .collect();
+
+    if m.is_present("json") {
+        println!("{}", serde_json::to_string_pretty(&entries)?);
+        return Ok(());
+    }
+
+    let total = entries.len();
+    println!(
+        "Found {} key{} in {}\n",
+        total,
+        if total == 1 { "" } else { "s" },
+        authorized_keys.path().display()
+    );
+    if total > 0 {
+        println!(
+            "{:<5}{:<36}{:<6}{:<52}{:<25}{:<25}{:<10}",
+            "#", "Type", "Bits", "Fingerprint", "Managed", "Options", "Comment"
+        );
+        println!("{:-<160}", "");
+        for entry in entries {
+            let managed = match (&entry.provenance, entry.managed) {
+                (Some(p), _) => match p.job {
+                    Some(j) => format!("{}:{} job {}", p.provider, p.user, j),
+                    None => format!("{}:{}", p.provider, p.user),
+                },
+                (None, true) => String::from("yes"),
+                (None, false) => String::from("no"),
+            };
+            println!(
+                "{:<5}{:<36}{:<6}{:<52}{:<25}{:<25}{}",
+                entry.index,
+                entry.key_type,
+                entry.bits,
+                entry.fingerprint,
+                managed,
+                entry.options,
+                entry.comment.unwrap_or_default()
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Removes keys from an authorized_keys file
+fn remove_key(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+    exit_if_root(user.as_ref())?;
+
+    let selected: Vec<String> = match m.values_of("keys") {
+        Some(v) => v.map(|x| x.to_owned()).collect(),
+        None => vec![],
+    };
+    let source: Option<Source> = match m.value_of("source") {
+        Some(s) => Some(s.parse()?),
+        None => None,
+    };
+
+    let config: Config = Config::load()?;
+    let authorized_keys =
+        AuthorizedKeys::find(user, &get_target(m, &config)?, &config)?;
+    let dry_run = m.is_present("dry_run");
+    let change = authorized_keys.remove_keys(
+        |index, key| {
+            selected.iter().any(|x| {
+                *x == index.to_string()
+                    || *x == key.fingerprint
+                    || format!("SHA256:{}", x) == key.fingerprint
+            }) || source.as_ref().is_some_and(|x| x.matches(key))
+        },
+        dry_run,
+    )?;
+
+    if change.is_empty() {
+        return Err(anyhow!("No keys matched"));
+    }
+    if dry_run {
+        print!("{}", change.diff());
+    } else {
+        for key in change.removed.iter() {
+            println!(
+                "Removed {} {} {}",
+                key.bits, key.fingerprint, key.key_type
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Removes duplicate keys, expired imported keys, malformed lines and stray whitespace from an authorized_keys file
+fn tidy(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+    exit_if_root(user.as_ref())?;
+
+    let config: Config = Config::load()?;
+    let authorized_keys =
+        AuthorizedKeys::find(user, &get_target(m, &config)?, &config)?;
+    let dry_run = m.is_present("dry_run");
+    let change = authorized_keys.tidy(dry_run)?;
+
+    if change.is_empty() {
+        println!("{} is already tidy", authorized_keys.path().display());
+        return Ok(());
+    }
+    if dry_run {
+        print!("{}", change.diff());
+    } else {
+        for removal in change.removals.iter() {
+            println!("Removed {}", removal);
+        }
+        println!("Tidied {}", authorized_keys.path().display());
+    }
+    Ok(())
+}
+
+/// Lists the backups of an authorized_keys file
+fn backups(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+    exit_if_root(user.as_ref())?;
+
+    let config: Config = Config::load()?;
+    let authorized_keys =
+        AuthorizedKeys::find(user, &get_target(m, &config)?, &config)?;
+    let backups: Vec<backup::Backup> = authorized_keys.backups().list()?;
+    let total = backups.len();
+    println!(
+        "Found {} backup{}.\n",
+        total,
+        if total == 1 { "" } else { "s" }
+    );
+    if total > 0 {
+        println!("{:<25}{:<45}", "Timestamp", "Path");
+        println!("{:-<70}", "");
+        for backup in backups {
+            println!("{:<25}{}", backup.timestamp, backup.path.display());
+        }
+    }
+    Ok(())
+}
+
+/// Restores an authorized_keys file from a backup
+fn rollback(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+    exit_if_root(user.as_ref())?;
+
+    let config: Config = Config::load()?;
+    let target = get_target(m, &config)?;
+    let authorized_keys =
+        AuthorizedKeys::find(user.as_ref(), &target, &config)?;
+    let backup = authorized_keys.backups().find(m.value_of("to"))?;
+    if m.is_present("dry_run") {
+        println!("Would restore backup: {}", backup.timestamp);
+    } else {
+        AuthorizedKeys::open(user, &target, &config)?.restore(&backup)?;
+        println!("Restored backup: {}", backup.timestamp);
+    }
+    Ok(())
+}
+
+/// Reports, and optionally repairs, permission problems with an authorized_keys file
+fn doctor(m: &ArgMatches) -> Result<()> {
+    let user: Option<String> = m.value_of("user").map(|x| x.to_owned());
+
+    let config: Config = Config::load()?;
+    let target = get_target(m, &config)?;
+    // Only create a missing file when asked to fix things
+    let authorized_keys = if m.is_present("fix") {
+        let authorized_keys = AuthorizedKeys::open(user, &target, &config)?;
+        authorized_keys.repair()?;
+        authorized_keys
+    } else {
+        AuthorizedKeys::find(user, &target, &config)?
+    };
+
+    let problems: Vec<perms::Problem> = authorized_keys.audit()?;
+    println!("Checked {}\n", authorized_keys.path().display());
+    if problems.is_empty() {
+        println!("No problems found");
+    }
+    for problem in problems.iter() {
+        println!("{}", problem);
+    }
+    if problems.iter().any(|x| x.refused) {
+        return Err(anyhow!("sshd will refuse the keys"));
+    }
+    Ok(())
+}
+
+/// Syncs the keys of every local user in the mapping file, carrying on past users that fail
+/// Keys apply imported before are removed once they are no longer mapped to the user, unless a mapping failed
+fn apply(m: &ArgMatches) -> Result<()> {
+    util::run_as_root(None)?;
+
+    let entries: Vec<mapping::Entry> =
+        mapping::load(m.value_of_t::<String>("file")?)?;
+    let config: Config = Config::load()?;
+    let target: Target = get_target(m, &config)?;
+    let policy: Policy = get_policy(m)?;
+    let dry_run = m.is_present("dry_run");
+    let network: Network =
+        Network::new().with_gitlab_hosts(&config.gitlab_hosts);
+    let database: Database = Database::open()?;
+
+    let mut mapped: BTreeMap<String, Vec<AuthorizedKey>> = BTreeMap::new();
+    let mut failed: usize = 0;
+    for entry in entries.iter() {
+        println!("{} <- {}", entry.user, entry.identities());
+        match entry_keys(entry, &network, &config, &policy, dry_run) {
+            Ok((users, keys)) => {
+                for user in users {
+                    mapped.entry(user).or_default().extend(keys.clone());
+                }
+            }
+            Err(e) => {
+                println!("Failed for {}: {}", entry.user, e);
+                failed += 1;
+            }
+        }
+    }
+
+    // A user may be missing keys only because the mapping that gives them failed
+    let replace = failed == 0;
+    if !replace {
+        println!("Not removing keys that are no longer mapped, since not every mapping applied");
+    }
+    let unmapped: Vec<String> = database
+        .mapped_users()?
+        .into_iter()
+        .filter(|x| replace && !mapped.contains_key(x))
+        .collect();
+    let total = mapped.len() + unmapped.len();
+    for (user, keys) in mapped {
+        match apply_user(&user, keys, &target, &config, replace, dry_run) {
+            Ok(()) if !dry_run => database.add_mapped_user(&user)?,
+            Ok(()) => (),
+            Err(e) => {
+                println!("Failed for {}: {}", user, e);
+                failed += 1;
+            }
+        }
+    }
+    for user in unmapped {
+        // Nothing is left to remove for a user that no longer exists
+        let exists = User::from_name(&user)?.is_some();
+        let applied = if exists {
+            apply_user(&user, vec![], &target, &config, true, dry_run)
+        } else {
+            Ok(())
+        };
+        match applied {
+            Ok(()) if !dry_run => database.remove_mapped_user(&user)?,
+            Ok(()) => (),
+            Err(e) => {
+                println!("Failed for {}: {}", user, e);
+                failed += 1;
+            }
+        }
+    }
+    if failed > 0 {
+        return Err(anyhow!(
+            "Failed to apply {} of {} mappings and users",
+            failed,
+            entries.len() + total
+        ));
+    }
+    Ok(())
+}
+
+/// Gets the local users of a mapping entry and the keys of its identities
+fn entry_keys(
+    entry: &mapping::Entry,
+    network: &Network,
+    config: &Config,
+    policy: &Policy,
+    dry_run: bool,
+) -> Result<(Vec<String>, Vec<AuthorizedKey>)> {
+    let users: Vec<String> = db::resolve_users(&entry.user)?;
+    for user in users.iter() {
+        if User::from_name(user)?.is_none() {
+            return Err(anyhow!("user '{}' does not exist on system", user));
+        }
+        exit_if_root(Some(user))?;
+    }
+
+    let urls: Vec<String> = entry.urls()?;
+    for url in urls.iter() {
+        daemon::verify_account(url, network, dry_run)?;
+    }
+    let keys: Vec<AuthorizedKey> = network.get_keys_urls(&urls)?;
+    let (keys, rejected) = policy::enforce(keys, &[&config.policy, policy]);
+    for rejection in rejected.iter() {
+        println!("{}", rejection);
+    }
+    let keys: Vec<AuthorizedKey> = hold_back(keys, config)?;
+    Ok((users, provenance::mark_mapped(keys)))
+}
+
+/// Writes the keys mapped to a local user, removing the keys apply imported before that are no longer mapped if replace is true
+fn apply_user(
+    user: &str,
+    keys: Vec<AuthorizedKey>,
+    target: &Target,
+    config: &Config,
+    replace: bool,
+    dry_run: bool,
+) -> Result<()> {
+    let mut keys = keys;
+    keys.sort_by(|a, b| a.fingerprint.cmp(&b.fingerprint));
+    keys.dedup_by(|a, b| a.same_key(b));
+
+    let authorized_keys = if dry_run {
+        AuthorizedKeys::find(Some(user), target, config)?
+    } else {
+        AuthorizedKeys::open(Some(user), target, config)?
+    };
+    let change = if replace {
+        authorized_keys.replace_mapped_keys(keys, dry_run)?
+    } else {
+        authorized_keys.write_keys(keys, &[], dry_run)?
+    };
+    if dry_run {
+        print!("{}", change.diff());
+    }
+    for conflict in change.conflicts.iter().filter(|_| !dry_run) {
+        println!("{}", conflict);
+    }
+    println!(
+        "{} {} new keys for {}",
+        if dry_run { "Found" } else { "Added" },
+        change.added.len(),
+        user
+    );
+    if !change.removed.is_empty() {
+        println!(
+            "{} {} keys no longer mapped or expired for {}",
+            if dry_run { "Would remove" } else { "Removed" },
+            change.removed.len(),
+            user
+        );
+    }
+    Ok(())
+}
+
+/// Prints the cached keys of a user for sshd, nothing is printed when there is no cache yet
+fn authorized_keys_command(m: &ArgMatches) -> Result<()> {
+    let account: String = m.value_of_t("account")?;
+    let fingerprint: Option<&str> = m.value_of("fingerprint");
+    if account.contains('/') || User::from_name(&account)?.is_none() {
+        return Err(anyhow!("No such user: {}", account));
+    }
+
+    let config: Config = Config::load()?;
+    let path = config.cache_dir.join(&account);
+    let keys: Vec<AuthorizedKey> = match fs::read_to_string(&path) {
+        Ok(text) => keys::parse(&text),
+        // Fail closed, keys are only ever served from what the daemon fetched
+        Err(e) if e.kind() == ErrorKind::NotFound => {
+            warn!("No cached keys for {} yet", account);
+            vec![]
+        }
+        Err(e) => {
+            return Err(e).with_context(|| {
+                format!("Error reading cached keys: {}", path.display())
+            })
+        }
+    };
+
+    let now = Utc::now();
+    for key in keys.iter().filter(|x| {
+        !x.is_expired(now) && fingerprint.is_none_or(|f| f == x.fingerprint)
+    }) {
+        println!("{}", key);
+    }
+    Ok(())
+}
+
 /// To be run by Systemd, runs until stopped
 fn daemon(m: &ArgMatches) -> Result<()> {
     let install = m.is_present("install");
@@ -249,10 +781,157 @@ fn daemon(m: &ArgMatches) -> Result<()> {
     Ok(())
 }
 
+/// Gets the local users from the arguments, the members of the group if one was given, empty if none were
+fn get_users(m: &ArgMatches) -> Result<Vec<String>> {
+    if let Some(g) = m.value_of("group") {
+        let members = util::group_members(g)?;
+        if members.is_empty() {
+            return Err(anyhow!("Group {} has no members", g));
+        }
+        return Ok(members);
+    }
+    Ok(match m.values_of("user") {
+        Some(v) => v.map(|x| x.to_owned()).collect(),
+        None => vec![],
+    })
+}
+
+/// Gets the sshd key options from the arguments, empty if none were provided
+/// The ttl is only taken by get, a job would store the expiry from when it was added
+fn get_options(m: &ArgMatches, ttl: Option<&str>) -> Result<Vec<KeyOption>> {
+    let mut options = match m.value_of("options") {
+        Some(o) => keys::parse_options(o)?,
+        None => vec![],
+    };
+    if m.is_present("cert_authority") {
+        options.push(KeyOption {
+            name: String::from("cert-authority"),
+            value: None,
+        });
+    }
+    if let Some(p) = m.value_of("principals") {
+        options.push(KeyOption {
+            name: String::from("principals"),
+            value: Some(p.to_owned()),
+        });
+    }
+    if let Some(t) = ttl {
+        let expires = Local::now()
+            .checked_add_signed(util::parse_duration(t)?)
+            .with_context(|| format!("Duration is too long: {}", t))?;
+        options.push(keys::expiry_time(expires));
+    }
+    if let Some(d) = m.value_of("expires") {
+        options.push(keys::expiry_time(util::parse_date(d)?));
+    }
+    keys::validate_options(&options)?;
+    Ok(options)
+}
+
+/// Gets the urls to retrieve keys from the arguments, GitHub if no provider or url was selected
+fn get_urls(m: &ArgMatches) -> Result<Vec<String>> {
+    let username: String = m.value_of_t("username")?;
+    if m.is_present("url") {
+        Url::parse(&username)
+            .with_context(|| format!("Invalid url: {}", username))?;
+        return Ok(vec![username]);
+    }
+
+    let mut gitlab_url: Option<Url> = None;
+    let gitlab: bool = if let Some(u) = m.value_of("gitlab") {
+        if !u.is_empty() {
+            gitlab_url = Some(Url::parse(u)?);
+        }
+        true
+    } else {
+        false
+    };
+
+    Ok(http::create_urls(
+        &username,
+        m.is_present("github"),
+        m.is_present("launchpad"),
+        gitlab,
+        gitlab_url,
+    ))
+}
+
+/// Gets where keys are written from the arguments
+/// The central directory defaults to the configured one, and the CA keys file to the one sshd reads
+fn get_target(m: &ArgMatches, config: &Config) -> Result<Target> {
+    if m.is_present("trusted_ca") {
+        return match m.value_of("trusted_ca") {
+            Some(f) => Ok(Target::TrustedCa(f.into())),
+            None => SshdConfig::load(&config.sshd_config)?
+                .trusted_user_ca_keys()
+                .map(Target::TrustedCa)
+                .with_context(|| {
+                    format!(
+                        "TrustedUserCAKeys is not set in {}, give the file with --trusted-ca=FILE",
+                        config.sshd_config.display()
+                    )
+                }),
+        };
+    }
+    if m.is_present("cache") {
+        return Ok(Target::Cache(config.cache_dir.clone()));
+    }
+    if !m.is_present("central") {
+        return Ok(Target::Home);
+    }
+    match m.value_of("central") {
+        Some(d) => Ok(Target::Central(d.into())),
+        None => Ok(Target::Central(config.central_dir.clone())),
+    }
+}
+
+/// Gets the key policy of the job from the arguments, empty if none was provided
+fn get_policy(m: &ArgMatches) -> Result<Policy> {
+    match m.value_of("policy") {
+        Some(p) => p.parse(),
+        None => Ok(Policy::default()),
+    }
+}
+
+/// Holds back the keys their provider created less than the quarantine period ago, for commands that have no job to remember when keys were first seen
+/// Keys without a creation time are kept and reported, only a job can hold them back from when it first sees them
+fn hold_back(
+    keys: Vec<AuthorizedKey>,
+    config: &Config,
+) -> Result<Vec<AuthorizedKey>> {
+    let period = match config.quarantine()? {
+        Some(p) => p,
+        None => return Ok(keys),
+    };
+    let now = Utc::now();
+    let mut trusted: Vec<AuthorizedKey> = vec![];
+    for key in keys {
+        match key.created {
+            Some(created) if now - created < period => println!(
+                "Holding {} {} back until {}, it was created {}",
+                key.fingerprint,
+                key.key_type,
+                (created + period).with_timezone(&Local),
+                created.with_timezone(&Local)
+            ),
+            Some(_) => trusted.push(key),
+            None => {
+                println!(
+                    "Not holding {} {} back, its provider has no creation time, only a job holds such keys back for {} from when it first sees them",
+                    key.fingerprint, key.key_type, config.quarantine
+                );
+                trusted.push(key);
+            }
+        }
+    }
+    Ok(trusted)
+}
+
 fn exit_if_root<S: AsRef<str>>(user: Option<S>) -> Result<()> {
     if let Some(u) = user {
-        // Unwrap shouldn't be an issue, should exist if we get here
-        if User::from_name(u.as_ref()).unwrap().unwrap().uid.is_root() {
+        let user = User::from_name(u.as_ref())?
+            .ok_or_else(|| anyhow!("No such user: {}", u.as_ref()))?;
+        if user.uid.is_root() {
             return Err(anyhow!("Adding keys to the root users authorized_keys file is not support, please refer too the following to learn about the risks.\nhttps://unix.stackexchange.com/questions/82626/why-is-root-login-via-ssh-so-bad-that-everyone-advises-to-disable-it"));
         }
     } else if Uid::current().is_root() {
@@ -0,0 +1,89 @@
+use anyhow::{anyhow, Context, Result};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+use url::Url;
+
+use super::http;
+use super::provenance::Source;
+
+/// The default path of the identity mapping file
+pub const MAPPING_PATH: &str = "/etc/keysync/users.toml";
+
+/// A local user, or %group, and the remote identities whose keys it receives
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub user: String,
+    pub sources: Vec<Source>,
+}
+
+impl Entry {
+    /// Gets the urls to retrieve the keys of every identity from
+    pub fn urls(&self) -> Result<Vec<String>> {
+        self.sources.iter().map(source_url).collect()
+    }
+
+    /// Formats the identities, as written in the mapping file
+    pub fn identities(&self) -> String {
+        self.sources
+            .iter()
+            .map(|x| x.to_string())
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+}
+
+/// Loads the mapping file Ex: deploy = ["gh:alice", "lp:asmith"]
+/// Returns the entries sorted by local user
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<Entry>> {
+    let path = path.as_ref();
+    let text = fs::read_to_string(path).with_context(|| {
+        format!("Error reading mapping file: {}", path.display())
+    })?;
+    parse(&text).with_context(|| {
+        format!("Error parsing mapping file: {}", path.display())
+    })
+}
+
+/// Parses the text of a mapping file, every identity needs a remote username
+pub fn parse(text: &str) -> Result<Vec<Entry>> {
+    let table: BTreeMap<String, Vec<String>> = toml::from_str(text)?;
+    let mut entries: Vec<Entry> = vec![];
+    for (user, identities) in table {
+        let mut sources: Vec<Source> = vec![];
+        for identity in identities.iter() {
+            let source: Source = identity.parse()?;
+            source_url(&source)
+                .with_context(|| format!("Invalid identity for {}", user))?;
+            sources.push(source);
+        }
+        entries.push(Entry { user, sources });
+    }
+    Ok(entries)
+}
+
+/// Gets the url to retrieve the keys of an identity from Ex: gh:alice, url:https://example.com/keys
+fn source_url(source: &Source) -> Result<String> {
+    let user = source
+        .user
+        .as_deref()
+        .ok_or_else(|| anyhow!("Missing remote username: {}", source))?;
+    if source.provider == "url" {
+        Url::parse(user).with_context(|| format!("Invalid url: {}", user))?;
+        return Ok(user.to_owned());
+    }
+    let provider = source.provider.as_str();
+    Ok(http::create_urls(
+        user,
+        provider == "github",
+        provider == "launchpad",
+        provider == "gitlab",
+        None,
+    )
+    .remove(0))
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/mapping.rs"]
+mod test;
@@ -0,0 +1,269 @@
+use anyhow::{anyhow, Context, Error, Result};
+use log::info;
+use nix::libc;
+use nix::unistd::{fchown, Gid, Uid, User};
+use std::os::unix::fs::{MetadataExt, OpenOptionsExt, PermissionsExt};
+use std::os::unix::io::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+
+/// The ownership and modes an authorized keys file and its directory should have
+#[derive(Debug, Clone, PartialEq)]
+pub struct Expected {
+    /// The user sshd checks the file for
+    pub user: Uid,
+    /// The home directory of the user, sshd stops checking directories there
+    pub home: PathBuf,
+    pub uid: Uid,
+    pub gid: Gid,
+    pub file_mode: u32,
+    /// The mode of the containing directory, None if keysync does not manage it
+    pub dir_mode: Option<u32>,
+}
+
+impl Expected {
+    /// Files in the users home directory, owned by the user and private
+    pub fn user(user: &User, file: &Path) -> Self {
+        // Only a directory inside the home directory belongs to keysync, Ex: ~/.ssh
+        let dir_mode = match file.parent() {
+            Some(d) if d.starts_with(&user.dir) && d != user.dir => Some(0o700),
+            _ => None,
+        };
+        Expected {
+            user: user.uid,
+            home: user.dir.clone(),
+            uid: user.uid,
+            gid: user.gid,
+            file_mode: 0o600,
+            dir_mode,
+        }
+    }
+
+    /// Files in a central directory, owned by root and readable by sshd running as the user
+    pub fn central(user: &User) -> Self {
+        Expected {
+            user: user.uid,
+            home: user.dir.clone(),
+            uid: Uid::from_raw(0),
+            gid: Gid::from_raw(0),
+            file_mode: 0o644,
+            dir_mode: Some(0o755),
+        }
+    }
+
+    /// The TrustedUserCAKeys file, owned by root and readable by sshd, its directory is left alone
+    pub fn trusted_ca(user: &User) -> Self {
+        Expected {
+            dir_mode: None,
+            ..Expected::central(user)
+        }
+    }
+}
+
+/// A permission or ownership problem found on a path
+#[derive(Debug, PartialEq)]
+pub struct Problem {
+    pub path: PathBuf,
+    pub message: String,
+    /// True if sshd's StrictModes will refuse the keys because of it
+    pub refused: bool,
+}
+
+/// Implements display for Problem
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.path.display(), self.message)?;
+        if self.refused {
+            write!(f, " (sshd will refuse the keys)")?;
+        }
+        Ok(())
+    }
+}
+
+/// Checks the authorized keys file, its directory and every directory up to the home directory
+/// The same ownership and write permission checks as sshd's StrictModes are made, plus the expected owner and modes
+pub fn audit(file: &Path, expected: &Expected) -> Result<Vec<Problem>> {
+    let mut problems: Vec<Problem> = vec![];
+    let metadata = match fs::symlink_metadata(file) {
+        Ok(m) => m,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            problems.push(Problem {
+                path: file.to_path_buf(),
+                message: String::from("does not exist"),
+                refused: false,
+            });
+            return Ok(problems);
+        }
+        Err(e) => {
+            return Err(Error::new(e).context(format!(
+                "Error reading metadata of: {}",
+                file.display()
+            )))
+        }
+    };
+    if metadata.file_type().is_symlink() {
+        problems.push(symlink_problem(file));
+        return Ok(problems);
+    }
+    if !metadata.is_file() {
+        problems.push(Problem {
+            path: file.to_path_buf(),
+            message: String::from("is not a regular file"),
+            refused: true,
+        });
+        return Ok(problems);
+    }
+    check(file, expected, Some(expected.file_mode), &mut problems)?;
+
+    for (i, dir) in file.ancestors().skip(1).enumerate() {
+        if dir.as_os_str().is_empty() {
+            break;
+        }
+        let mode = if i == 0 { expected.dir_mode } else { None };
+        check(dir, expected, mode, &mut problems)?;
+        if dir == expected.home {
+            break; // Like sshd, directories above the home directory are not checked
+        }
+    }
+    Ok(problems)
+}
+
+/// Finds a symbolic link keysync should not write through, the file itself or, inside the home directory, a directory above it
+/// The home directory itself, and directories outside of it, belong to root so they are trusted
+pub fn find_symlink(file: &Path, home: Option<&Path>) -> Option<PathBuf> {
+    let inside_home = home.filter(|h| file.starts_with(h));
+    for path in file.ancestors() {
+        if path.as_os_str().is_empty() || Some(path) == inside_home {
+            break;
+        }
+        if fs::symlink_metadata(path).is_ok_and(|x| x.file_type().is_symlink())
+        {
+            return Some(path.to_path_buf());
+        }
+        if inside_home.is_none() {
+            break;
+        }
+    }
+    None
+}
+
+/// Sets the expected owner and modes on the authorized keys file, and its directory if keysync manages it
+/// Refuses to repair through a symbolic link, which could point at any file
+pub fn repair(file: &Path, expected: &Expected) -> Result<()> {
+    if let Some(link) = find_symlink(file, Some(&expected.home)) {
+        return Err(anyhow!(
+            "Refusing to repair through the symbolic link: {}",
+            link.display()
+        ));
+    }
+    set(file, expected, expected.file_mode)?;
+    if let (Some(dir), Some(mode)) = (file.parent(), expected.dir_mode) {
+        set(dir, expected, mode)?;
+    }
+    Ok(())
+}
+
+/// Checks a single path, with the expected owner and mode if it is managed by keysync
+fn check(
+    path: &Path,
+    expected: &Expected,
+    mode: Option<u32>,
+    problems: &mut Vec<Problem>,
+) -> Result<()> {
+    let mut metadata = fs::symlink_metadata(path).with_context(|| {
+        format!("Error reading metadata of: {}", path.display())
+    })?;
+    if metadata.file_type().is_symlink() {
+        if path != expected.home {
+            problems.push(symlink_problem(path));
+            return Ok(());
+        }
+        // The home directory belongs to root, so it may be a link
+        metadata = fs::metadata(path).with_context(|| {
+            format!("Error reading metadata of: {}", path.display())
+        })?;
+    }
+    let uid = Uid::from_raw(metadata.uid());
+    let gid = Gid::from_raw(metadata.gid());
+    let actual = metadata.mode() & 0o7777;
+    let mut problem = |message: String, refused: bool| {
+        problems.push(Problem {
+            path: path.to_path_buf(),
+            message,
+            refused,
+        })
+    };
+
+    if !uid.is_root() && uid != expected.user {
+        problem(format!("is owned by uid {}", uid), true);
+    } else if mode.is_some() && (uid != expected.uid || gid != expected.gid) {
+        problem(
+            format!(
+                "is owned by {}:{}, expected {}:{}",
+                uid, gid, expected.uid, expected.gid
+            ),
+            false,
+        );
+    }
+
+    if actual & 0o022 != 0 {
+        problem(
+            format!("mode {:04o} is group or world writable", actual),
+            true,
+        );
+    } else if let Some(m) = mode.filter(|m| *m != actual) {
+        problem(format!("mode {:04o}, expected {:04o}", actual, m), false);
+    }
+    Ok(())
+}
+
+/// The problem reported for a symbolic link, which keysync will not write or repair through
+fn symlink_problem(path: &Path) -> Problem {
+    Problem {
+        path: path.to_path_buf(),
+        message: String::from(
+            "is a symbolic link, keysync will not write through it",
+        ),
+        refused: false,
+    }
+}
+
+/// Sets the owner and mode of a path if they differ
+/// The path is opened without following a symbolic link, and changed through the open file
+fn set(path: &Path, expected: &Expected, mode: u32) -> Result<()> {
+    let file = fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+        .with_context(|| format!("Failed to open [{}]", path.display()))?;
+    let metadata = file.metadata().with_context(|| {
+        format!("Error reading metadata of: {}", path.display())
+    })?;
+    if metadata.uid() != expected.uid.as_raw()
+        || metadata.gid() != expected.gid.as_raw()
+    {
+        fchown(file.as_raw_fd(), Some(expected.uid), Some(expected.gid))
+            .with_context(|| {
+                format!("Failed to set the ownership of [{}]", path.display())
+            })?;
+        info!(
+            "Set the owner of {} to {}:{}",
+            path.display(),
+            expected.uid,
+            expected.gid
+        );
+    }
+    if metadata.mode() & 0o7777 != mode {
+        file.set_permissions(fs::Permissions::from_mode(mode))
+            .with_context(|| {
+                format!("Failed to set the mode of [{}]", path.display())
+            })?;
+        info!("Set the mode of {} to {:04o}", path.display(), mode);
+    }
+    Ok(())
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/perms.rs"]
+mod test;
@@ -0,0 +1,130 @@
+use anyhow::{anyhow, Error, Result};
+use serde::Deserialize;
+use std::fmt;
+use std::str::FromStr;
+
+use super::keys::AuthorizedKey;
+use super::sshd::wildcard_match;
+
+/// Requirements an imported key has to meet, an empty policy accepts every key
+/// Written in the config file as a table, and for jobs as a spec Ex: allow=ssh-ed25519,sk-* deny=ssh-dss min-rsa-bits=3072
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Policy {
+    /// Key type patterns that are allowed, where `*` and `?` are wildcards, all types if empty
+    pub allow: Vec<String>,
+    /// Key type patterns that are rejected, takes priority over allow
+    pub deny: Vec<String>,
+    /// Minimum size of RSA keys, including RSA certificates
+    pub min_rsa_bits: u32,
+}
+
+/// A key that failed a policy, with the reason
+#[derive(Debug, PartialEq)]
+pub struct Rejection {
+    pub key: AuthorizedKey,
+    pub reason: String,
+}
+
+/// Implements display for Rejection
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Rejected {} {}: {}",
+            self.key.fingerprint, self.key.key_type, self.reason
+        )
+    }
+}
+
+/// Implements display for Policy, formatted as a spec
+impl fmt::Display for Policy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts: Vec<String> = vec![];
+        if !self.allow.is_empty() {
+            parts.push(format!("allow={}", self.allow.join(",")));
+        }
+        if !self.deny.is_empty() {
+            parts.push(format!("deny={}", self.deny.join(",")));
+        }
+        if self.min_rsa_bits > 0 {
+            parts.push(format!("min-rsa-bits={}", self.min_rsa_bits));
+        }
+        write!(f, "{}", parts.join(" "))
+    }
+}
+
+/// FromStr implementation, parses a spec of space separated rules
+impl FromStr for Policy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut policy = Policy::default();
+        for rule in s.split_whitespace() {
+            let (name, value) = rule.split_once('=').ok_or_else(|| {
+                anyhow!("Policy rule needs a value: {}", rule)
+            })?;
+            let patterns = || -> Vec<String> {
+                value
+                    .split(',')
+                    .filter(|x| !x.is_empty())
+                    .map(|x| x.to_owned())
+                    .collect()
+            };
+            match name {
+                "allow" => policy.allow = patterns(),
+                "deny" => policy.deny = patterns(),
+                "min-rsa-bits" => {
+                    policy.min_rsa_bits = value.parse().map_err(|_| {
+                        anyhow!("min-rsa-bits must be a number: {}", value)
+                    })?
+                }
+                _ => return Err(anyhow!("Unknown policy rule: {}", name)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+impl Policy {
+    /// Checks a key against the policy, returns the reason if it is rejected
+    pub fn check(&self, key: &AuthorizedKey) -> Result<(), String> {
+        let matches = |patterns: &[String]| {
+            patterns.iter().any(|x| wildcard_match(x, &key.key_type))
+        };
+        if matches(&self.deny) {
+            return Err(format!("type {} is denied", key.key_type));
+        }
+        if !self.allow.is_empty() && !matches(&self.allow) {
+            return Err(format!("type {} is not allowed", key.key_type));
+        }
+        if key.key_type.starts_with("ssh-rsa") && key.bits < self.min_rsa_bits {
+            return Err(format!(
+                "RSA key has {} bits, at least {} are required",
+                key.bits, self.min_rsa_bits
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Splits the keys into those meeting every policy, and the rejected ones with the first reason found
+pub fn enforce(
+    keys: Vec<AuthorizedKey>,
+    policies: &[&Policy],
+) -> (Vec<AuthorizedKey>, Vec<Rejection>) {
+    let mut accepted: Vec<AuthorizedKey> = vec![];
+    let mut rejected: Vec<Rejection> = vec![];
+    for key in keys {
+        match policies.iter().find_map(|x| x.check(&key).err()) {
+            Some(reason) => rejected.push(Rejection { key, reason }),
+            None => accepted.push(key),
+        }
+    }
+    (accepted, rejected)
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/policy.rs"]
+mod test;
@@ -0,0 +1,284 @@
+use anyhow::{anyhow, Error};
+use chrono::Utc;
+use serde::Serialize;
+use std::fmt;
+use std::str::FromStr;
+use url::Url;
+
+use super::keys::AuthorizedKey;
+
+/// The start of the comment keysync writes on every key it imports
+const PREFIX: &str = "keysync";
+
+/// The comment older versions of keysync wrote on every key, which has no details
+const LEGACY_COMMENT: &str = "#ssh-import keysync";
+
+/// Where an imported key came from, stored in the comment of its authorized_keys line
+/// Ex: keysync provider=github user=bob job=3 imported=2022-06-01T14:25:01Z comment=bob@laptop
+/// Keys apply imports from the mapping file have mapped=true instead of a job
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Provenance {
+    /// github, gitlab, launchpad or url for any other source
+    pub provider: String,
+    /// The remote username, or the whole url for other sources
+    pub user: String,
+    /// The host of a self hosted provider
+    pub host: Option<String>,
+    pub job: Option<u32>,
+    /// True if the key was imported by apply from the mapping file
+    pub mapped: bool,
+    pub imported: Option<String>,
+    /// The comment the key had at the provider
+    pub comment: Option<String>,
+}
+
+/// Implements display for Provenance, formatted as an authorized_keys comment
+impl fmt::Display for Provenance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} provider={} user={}",
+            PREFIX, self.provider, self.user
+        )?;
+        if let Some(h) = &self.host {
+            write!(f, " host={}", h)?;
+        }
+        if let Some(j) = &self.job {
+            write!(f, " job={}", j)?;
+        }
+        if self.mapped {
+            write!(f, " mapped=true")?;
+        }
+        if let Some(i) = &self.imported {
+            write!(f, " imported={}", i)?;
+        }
+        // The original comment may contain spaces, so it is always last
+        if let Some(c) = &self.comment {
+            write!(f, " comment={}", c)?;
+        }
+        Ok(())
+    }
+}
+
+impl Provenance {
+    /// Creates the provenance of keys being imported now from a url, by an optional job
+    /// A .keys url is only attributed to GitLab on one of the hosts known to run it
+    pub fn new(url: &str, job: Option<u32>, gitlab_hosts: &[String]) -> Self {
+        let mut provenance = from_url(url, gitlab_hosts);
+        provenance.job = job;
+        provenance.imported =
+            Some(Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string());
+        provenance
+    }
+
+    /// Parses the provenance back from a key comment, None if keysync did not write it
+    /// Keys tagged by older versions of keysync are managed but have no details, so they are also None
+    pub fn from_comment(comment: &str) -> Option<Self> {
+        let mut rest =
+            comment.trim().strip_prefix(PREFIX)?.strip_prefix(' ')?;
+        let mut provenance = Provenance::default();
+        while !rest.is_empty() {
+            if let Some(c) = rest.strip_prefix("comment=") {
+                provenance.comment = Some(c.to_owned());
+                break;
+            }
+            let (field, tail) = rest.split_once(' ').unwrap_or((rest, ""));
+            rest = tail.trim_start();
+            let (key, value) = field.split_once('=')?;
+            match key {
+                "provider" => provenance.provider = value.to_owned(),
+                "user" => provenance.user = value.to_owned(),
+                "host" => provenance.host = Some(value.to_owned()),
+                "job" => provenance.job = value.parse().ok(),
+                "mapped" => provenance.mapped = value == "true",
+                "imported" => provenance.imported = Some(value.to_owned()),
+                _ => (), // Written by a newer version
+            }
+        }
+        if provenance.provider.is_empty() {
+            return None;
+        }
+        Some(provenance)
+    }
+
+    /// Tags the keys with this provenance, keeping their original comment
+    pub fn tag(&self, keys: Vec<AuthorizedKey>) -> Vec<AuthorizedKey> {
+        keys.into_iter()
+            .map(|mut x| {
+                let provenance = Provenance {
+                    comment: x.comment.take(),
+                    ..self.clone()
+                };
+                x.comment = Some(provenance.to_string());
+                x
+            })
+            .collect()
+    }
+}
+
+/// A provider and optional remote username to select keys by Ex: gh:bob, gitlab, url:https://example.com/keys
+#[derive(Debug, PartialEq)]
+pub struct Source {
+    pub provider: String,
+    pub user: Option<String>,
+}
+
+/// Implements display for Source, which parses back to the same source
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.user {
+            Some(user) => write!(f, "{}:{}", self.provider, user),
+            None => write!(f, "{}", self.provider),
+        }
+    }
+}
+
+/// The source a key was imported from
+impl From<&Provenance> for Source {
+    fn from(provenance: &Provenance) -> Self {
+        Source {
+            provider: provenance.provider.clone(),
+            user: Some(provenance.user.clone()),
+        }
+    }
+}
+
+/// FromStr implementation, the provider can be abbreviated as gh, gl or lp
+impl FromStr for Source {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (provider, user) = match s.split_once(':') {
+            Some((p, u)) => (p, Some(u.to_owned())),
+            None => (s, None),
+        };
+        let provider = match provider {
+            "gh" | "github" => "github",
+            "gl" | "gitlab" => "gitlab",
+            "lp" | "launchpad" => "launchpad",
+            "url" => "url",
+            _ => return Err(anyhow!("Unknown provider: {}", provider)),
+        };
+        Ok(Source {
+            provider: provider.to_owned(),
+            user: user.filter(|x| !x.is_empty()),
+        })
+    }
+}
+
+impl Source {
+    /// Returns true if the key was imported from this source
+    pub fn matches(&self, key: &AuthorizedKey) -> bool {
+        let provenance = match key.comment.as_deref() {
+            Some(c) => Provenance::from_comment(c),
+            None => None,
+        };
+        match provenance {
+            Some(p) => {
+                p.provider == self.provider
+                    && self.user.as_ref().is_none_or(|x| *x == p.user)
+            }
+            None => false,
+        }
+    }
+}
+
+/// Returns true if the key was imported by keysync, including by older versions
+pub fn is_managed(key: &AuthorizedKey) -> bool {
+    match &key.comment {
+        Some(c) => {
+            c.trim() == LEGACY_COMMENT || Provenance::from_comment(c).is_some()
+        }
+        None => false,
+    }
+}
+
+/// Returns true if the key was imported by the job
+pub fn is_from_job(key: &AuthorizedKey, job: u32) -> bool {
+    key.comment
+        .as_deref()
+        .and_then(Provenance::from_comment)
+        .is_some_and(|x| x.job == Some(job))
+}
+
+/// Returns true if the key was imported by apply from the mapping file
+pub fn is_mapped(key: &AuthorizedKey) -> bool {
+    key.comment
+        .as_deref()
+        .and_then(Provenance::from_comment)
+        .is_some_and(|x| x.mapped)
+}
+
+/// Marks imported keys as coming from the mapping file, so apply can remove them once they are no longer mapped
+pub fn mark_mapped(keys: Vec<AuthorizedKey>) -> Vec<AuthorizedKey> {
+    keys.into_iter()
+        .map(|mut x| {
+            let provenance =
+                x.comment.as_deref().and_then(Provenance::from_comment);
+            if let Some(p) = provenance {
+                x.comment = Some(Provenance { mapped: true, ..p }.to_string());
+            }
+            x
+        })
+        .collect()
+}
+
+/// Returns true if the url is on one of the hosts known to run GitLab
+pub fn is_gitlab_host(url: &str, gitlab_hosts: &[String]) -> bool {
+    Url::parse(url)
+        .ok()
+        .and_then(|x| x.host_str().map(|h| h.to_owned()))
+        .is_some_and(|h| {
+            gitlab_hosts.iter().any(|x| x.eq_ignore_ascii_case(&h))
+        })
+}
+
+/// Works out the provider and remote username from a keys url, any host that is not known is recorded as url
+fn from_url(url: &str, gitlab_hosts: &[String]) -> Provenance {
+    let other = Provenance {
+        provider: String::from("url"),
+        user: url.to_owned(),
+        ..Provenance::default()
+    };
+    let parsed = match Url::parse(url) {
+        Ok(u) => u,
+        Err(_) => return other,
+    };
+    let host = parsed.host_str().unwrap_or_default();
+    let last = parsed
+        .path_segments()
+        .and_then(|mut x| x.rfind(|s| !s.is_empty()))
+        .unwrap_or_default();
+
+    let (provider, user) = if host == "launchpad.net" {
+        match parsed.path().strip_suffix("/+sshkeys") {
+            Some(p) => ("launchpad", p.trim_start_matches("/~")),
+            None => return other,
+        }
+    } else if let Some(user) = last.strip_suffix(".keys") {
+        if host == "github.com" {
+            ("github", user)
+        } else if is_gitlab_host(url, gitlab_hosts) {
+            ("gitlab", user)
+        } else {
+            return other;
+        }
+    } else {
+        return other;
+    };
+    Provenance {
+        provider: provider.to_owned(),
+        user: user.to_owned(),
+        host: if provider == "gitlab" && host != "gitlab.com" {
+            Some(host.to_owned())
+        } else {
+            None
+        },
+        ..Provenance::default()
+    }
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/provenance.rs"]
+mod test;
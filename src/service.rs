@@ -74,7 +74,7 @@ fn get_service_status() -> Result<KeysyncService> {
 
     match code {
         0 => Ok(KeysyncService::Active),
-        1 | 2 | 3 => Ok(KeysyncService::Stopped),
+        1..=3 => Ok(KeysyncService::Stopped),
         _ => Ok(KeysyncService::NotInstalled), // Aka 4
     }
 }
@@ -0,0 +1,259 @@
+use anyhow::{anyhow, Error, Result};
+use log::{debug, warn};
+use nix::unistd::User;
+use std::path::{Path, PathBuf};
+use std::{fs, io};
+
+/// The directory relative Include paths are resolved against
+const SSH_DIR: &str = "/etc/ssh";
+
+/// The AuthorizedKeysFile sshd uses when it is not configured
+const DEFAULT_AUTHORIZED_KEYS_FILES: &[&str] =
+    &[".ssh/authorized_keys", ".ssh/authorized_keys2"];
+
+/// How deep Include directives can be nested, the same limit sshd uses
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The settings keysync needs from sshd_config(5)
+/// Only the defaults are read, anything inside a Match block is ignored
+#[derive(Debug, Default, PartialEq)]
+pub struct SshdConfig {
+    authorized_keys_file: Option<Vec<String>>,
+    trusted_user_ca_keys: Option<String>,
+}
+
+impl SshdConfig {
+    /// Reads the sshd config from a given path, if it does not exist sshd's defaults are used
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let mut config = SshdConfig::default();
+        if path.as_ref().is_file() {
+            config.read_file(path.as_ref(), 0)?;
+        } else {
+            debug!(
+                "sshd config {} does not exist, using defaults",
+                path.as_ref().display()
+            );
+        }
+        Ok(config)
+    }
+
+    /// Gets the authorized keys files sshd reads for the user, with tokens expanded and relative paths resolved against the home directory
+    /// Returns an empty list if sshd is configured not to read any
+    pub fn authorized_keys_files(&self, user: &User) -> Result<Vec<PathBuf>> {
+        let files: Vec<String> = match &self.authorized_keys_file {
+            Some(f) => f.clone(),
+            None => DEFAULT_AUTHORIZED_KEYS_FILES
+                .iter()
+                .map(|x| x.to_string())
+                .collect(),
+        };
+        if files.len() == 1 && files[0].eq_ignore_ascii_case("none") {
+            return Ok(vec![]);
+        }
+        files
+            .iter()
+            .map(|x| {
+                let path = PathBuf::from(expand_tokens(x, user)?);
+                Ok(if path.is_absolute() {
+                    path
+                } else {
+                    user.dir.join(path)
+                })
+            })
+            .collect()
+    }
+
+    /// Gets the file of CA keys trusted to sign user certificates, None if sshd is not configured with one
+    pub fn trusted_user_ca_keys(&self) -> Option<PathBuf> {
+        match self.trusted_user_ca_keys.as_deref() {
+            Some(f) if !f.eq_ignore_ascii_case("none") => {
+                Some(PathBuf::from(f))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads a config file, recursively reading any included files
+    /// A file that can not be read for lack of permission is skipped with a warning
+    fn read_file(&mut self, path: &Path, depth: usize) -> Result<()> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(anyhow!(
+                "Too many nested includes in sshd config: {}",
+                path.display()
+            ));
+        }
+        let text = match fs::read_to_string(path) {
+            Ok(t) => t,
+            // keysync may not be running as root, so fall back to the defaults rather than failing
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+                warn!(
+                    "Permission denied reading sshd config {}, ignoring it, the settings used may not match sshd's",
+                    path.display()
+                );
+                return Ok(());
+            }
+            Err(e) => {
+                return Err(Error::new(e).context(format!(
+                    "Error reading sshd config: {}",
+                    path.display()
+                )))
+            }
+        };
+        self.parse(&text, depth)
+    }
+
+    /// Parses the text of a config file, stopping at the first Match block
+    fn parse(&mut self, text: &str, depth: usize) -> Result<()> {
+        for line in text.lines() {
+            let args = split_args(line);
+            let (keyword, args) = match args.split_first() {
+                Some((k, a)) => (k.to_lowercase(), a),
+                None => continue,
+            };
+            match keyword.as_str() {
+                "match" => break, // Everything after is conditional
+                "include" => {
+                    for pattern in args {
+                        for file in glob(pattern)? {
+                            self.read_file(&file, depth + 1)?;
+                        }
+                    }
+                }
+                // Like sshd, the first value obtained is used
+                "authorizedkeysfile" if self.authorized_keys_file.is_none() => {
+                    self.authorized_keys_file = Some(args.to_vec());
+                }
+                "trustedusercakeys" if self.trusted_user_ca_keys.is_none() => {
+                    self.trusted_user_ca_keys = args.first().cloned();
+                }
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Splits a config line into the keyword and its arguments, handling quotes, `=` after the keyword and comments
+fn split_args(line: &str) -> Vec<String> {
+    let line = line.trim();
+    // The keyword may be separated from its arguments by an equals sign
+    let line = match line.find(|c: char| c.is_whitespace() || c == '=') {
+        Some(i) => {
+            let (keyword, rest) = line.split_at(i);
+            let rest = rest.trim_start();
+            let rest = rest.strip_prefix('=').unwrap_or(rest);
+            format!("{} {}", keyword, rest)
+        }
+        None => line.to_owned(),
+    };
+
+    let mut args: Vec<String> = vec![];
+    let mut chars = line.chars().peekable();
+    loop {
+        while chars.next_if(|c| c.is_whitespace()).is_some() {}
+        match chars.peek() {
+            None | Some('#') => break,
+            Some('"') => {
+                chars.next();
+                args.push(chars.by_ref().take_while(|c| *c != '"').collect());
+            }
+            Some(_) => {
+                let mut arg = String::new();
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    arg.push(c);
+                }
+                args.push(arg);
+            }
+        }
+    }
+    args
+}
+
+/// Expands the %h, %u, %U and %% tokens of an AuthorizedKeysFile path
+fn expand_tokens(path: &str, user: &User) -> Result<String> {
+    let mut expanded = String::new();
+    let mut chars = path.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            expanded.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('h') => expanded.push_str(&user.dir.to_string_lossy()),
+            Some('u') => expanded.push_str(&user.name),
+            Some('U') => expanded.push_str(&user.uid.to_string()),
+            Some('%') => expanded.push('%'),
+            Some(t) => {
+                return Err(anyhow!("Unknown token %{} in: {}", t, path))
+            }
+            None => return Err(anyhow!("Trailing % in: {}", path)),
+        }
+    }
+    Ok(expanded)
+}
+
+/// Finds the files matching an Include pattern, only the file name may contain wildcards
+fn glob(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = Path::new(SSH_DIR).join(pattern); // Joining an absolute path replaces the base
+    let name = match path.file_name() {
+        Some(n) => n.to_string_lossy().to_string(),
+        None => return Ok(vec![]),
+    };
+    if !name.contains(['*', '?']) {
+        return Ok(if path.is_file() { vec![path] } else { vec![] });
+    }
+
+    let dir = path.parent().unwrap_or_else(|| Path::new("/"));
+    if !dir.is_dir() {
+        warn!("sshd Include directory does not exist: {}", dir.display());
+        return Ok(vec![]);
+    }
+    let entries = match fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(e) if e.kind() == io::ErrorKind::PermissionDenied => {
+            warn!(
+                "Permission denied reading sshd Include directory {}, ignoring it",
+                dir.display()
+            );
+            return Ok(vec![]);
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let mut files: Vec<PathBuf> = entries
+        .filter_map(|x| x.ok())
+        .filter(|x| wildcard_match(&name, &x.file_name().to_string_lossy()))
+        .map(|x| x.path())
+        .filter(|x| x.is_file())
+        .collect();
+    files.sort(); // sshd reads included files in lexical order
+    Ok(files)
+}
+
+/// Matches a name against a pattern where `*` matches any run of characters and `?` matches any one character
+pub fn wildcard_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((bp, bn)) = backtrack {
+            p = bp + 1;
+            n = bn + 1;
+            backtrack = Some((bp, bn + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/sshd.rs"]
+mod test;
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Context, Result};
+use sha2::{Digest, Sha256};
+
+/// The suffix of certificate key types, the certified key type is the part before it
+const CERT_SUFFIX: &str = "-cert-v01@openssh.com";
+
+/// The details of a decoded public key blob
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyData {
+    /// Size of the key in bits, Ex: the RSA modulus length
+    pub bits: u32,
+    /// SHA256 fingerprint, formatted like ssh-keygen -l Ex: SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8
+    pub fingerprint: String,
+}
+
+/// Decodes a base64 key blob in the SSH wire format (RFC 4253), checking it is a valid key of the declared type
+pub fn decode(key_type: &str, blob: &str) -> Result<KeyData> {
+    let bytes = base64::decode(blob).context("Key data is not valid base64")?;
+    let mut reader = Reader::new(&bytes);
+    let embedded = reader.string_utf8()?;
+    if embedded != key_type {
+        return Err(anyhow!(
+            "Key data is for type {}, not {}",
+            embedded,
+            key_type
+        ));
+    }
+
+    match key_type.strip_suffix(CERT_SUFFIX) {
+        Some(plain_type) => {
+            reader.string()?; // Nonce
+            let start = reader.position();
+            let bits = read_key(plain_type, &mut reader)?;
+            // Like ssh-keygen, certificates are identified by the key they certify
+            let mut plain = encode_string(plain_type.as_bytes());
+            plain.extend_from_slice(&bytes[start..reader.position()]);
+            Ok(KeyData {
+                bits,
+                fingerprint: fingerprint(&plain),
+            })
+        }
+        None => {
+            let bits = read_key(key_type, &mut reader)?;
+            if !reader.is_empty() {
+                return Err(anyhow!("Key data has trailing bytes"));
+            }
+            Ok(KeyData {
+                bits,
+                fingerprint: fingerprint(&bytes),
+            })
+        }
+    }
+}
+
+/// Reads and checks the public key fields of a plain key type, returns the size of the key in bits
+fn read_key(key_type: &str, reader: &mut Reader) -> Result<u32> {
+    match key_type {
+        "ssh-rsa" => {
+            let e = reader.mpint()?;
+            let n = reader.mpint()?;
+            if e.is_empty() || n.last().is_none_or(|x| x & 1 == 0) {
+                return Err(anyhow!("Invalid RSA public key"));
+            }
+            Ok(bit_length(n))
+        }
+        "ssh-dss" => {
+            let p = reader.mpint()?;
+            for _ in 0..3 {
+                if reader.mpint()?.is_empty() {
+                    return Err(anyhow!("Invalid DSA public key"));
+                }
+            }
+            Ok(bit_length(p))
+        }
+        "ssh-ed25519" => {
+            read_ed25519(reader)?;
+            Ok(256)
+        }
+        "sk-ssh-ed25519@openssh.com" => {
+            read_ed25519(reader)?;
+            reader.string()?; // Application
+            Ok(256)
+        }
+        "ecdsa-sha2-nistp256"
+        | "ecdsa-sha2-nistp384"
+        | "ecdsa-sha2-nistp521" => {
+            read_ecdsa(&key_type["ecdsa-sha2-".len()..], reader)
+        }
+        "sk-ecdsa-sha2-nistp256@openssh.com" => {
+            let bits = read_ecdsa("nistp256", reader)?;
+            reader.string()?; // Application
+            Ok(bits)
+        }
+        "ssh-xmss@openssh.com" => {
+            reader.string_utf8()?; // Parameter set name
+            let public = reader.string()?;
+            Ok(8 * public.len() as u32)
+        }
+        _ => Err(anyhow!("Unknown key type: {}", key_type)),
+    }
+}
+
+/// Reads an ed25519 public key, which is always 32 bytes
+fn read_ed25519(reader: &mut Reader) -> Result<()> {
+    if reader.string()?.len() != 32 {
+        return Err(anyhow!("Invalid ed25519 public key length"));
+    }
+    Ok(())
+}
+
+/// Reads an ECDSA curve name and point, the point must be uncompressed and sized for the curve
+fn read_ecdsa(curve: &str, reader: &mut Reader) -> Result<u32> {
+    let (bits, coordinate) = match curve {
+        "nistp256" => (256, 32),
+        "nistp384" => (384, 48),
+        "nistp521" => (521, 66),
+        _ => return Err(anyhow!("Unknown curve: {}", curve)),
+    };
+    let embedded = reader.string_utf8()?;
+    if embedded != curve {
+        return Err(anyhow!("Key curve is {}, expected {}", embedded, curve));
+    }
+    let point = reader.string()?;
+    if point.len() != 1 + 2 * coordinate || point[0] != 0x04 {
+        return Err(anyhow!("Invalid ECDSA point for curve {}", curve));
+    }
+    Ok(bits)
+}
+
+/// Formats the SHA256 fingerprint of a key blob
+fn fingerprint(blob: &[u8]) -> String {
+    let digest = Sha256::digest(blob);
+    format!(
+        "SHA256:{}",
+        base64::encode_config(digest, base64::STANDARD_NO_PAD)
+    )
+}
+
+/// Encodes bytes as an SSH string, prefixed by their length
+fn encode_string(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = (bytes.len() as u32).to_be_bytes().to_vec();
+    encoded.extend_from_slice(bytes);
+    encoded
+}
+
+/// The amount of significant bits of a big endian unsigned integer
+fn bit_length(int: &[u8]) -> u32 {
+    let int: Vec<u8> = int.iter().copied().skip_while(|x| *x == 0).collect();
+    match int.first() {
+        Some(first) => (int.len() as u32 - 1) * 8 + (8 - first.leading_zeros()),
+        None => 0,
+    }
+}
+
+/// Reads the fields of an SSH wire format buffer
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, position: 0 }
+    }
+
+    fn position(&self) -> usize {
+        self.position
+    }
+
+    fn is_empty(&self) -> bool {
+        self.position == self.data.len()
+    }
+
+    /// Reads a length prefixed byte string
+    fn string(&mut self) -> Result<&'a [u8]> {
+        let header = self
+            .data
+            .get(self.position..self.position + 4)
+            .context("Key data is truncated")?;
+        let length =
+            u32::from_be_bytes([header[0], header[1], header[2], header[3]])
+                as usize;
+        let start = self.position + 4;
+        let string = self
+            .data
+            .get(start..start.saturating_add(length))
+            .context("Key data is truncated")?;
+        self.position = start + length;
+        Ok(string)
+    }
+
+    /// Reads a length prefixed UTF-8 string
+    fn string_utf8(&mut self) -> Result<&'a str> {
+        std::str::from_utf8(self.string()?)
+            .context("Key data contains an invalid name")
+    }
+
+    /// Reads a positive multiple precision integer, negative values are an error
+    fn mpint(&mut self) -> Result<&'a [u8]> {
+        let int = self.string()?;
+        if int.first().is_some_and(|x| x & 0x80 != 0) {
+            return Err(anyhow!("Key data contains a negative integer"));
+        }
+        Ok(int)
+    }
+}
+
+/// Unit Tests
+#[cfg(test)]
+#[path = "./tests/sshkey.rs"]
+mod test;
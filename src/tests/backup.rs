@@ -0,0 +1,95 @@
+use super::*;
+use assert_fs::prelude::*;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::time::Duration;
+
+/// Backs up the file from its open directory, as AuthorizedKeys does
+fn create(backups: &Backups) -> Result<Option<Backup>> {
+    let parent = backups.file.parent().unwrap();
+    backups.create(&Dir::open(parent, None)?.unwrap())
+}
+
+/// Tests that nothing is backed up when the file does not exist or backups are disabled
+#[test]
+fn test_no_backup() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let backups = Backups::new(file.path(), 3);
+    assert_eq!(create(&backups).unwrap(), None);
+    file.write_str("foo").unwrap();
+    assert_eq!(create(&Backups::new(file.path(), 0)).unwrap(), None);
+    assert_eq!(backups.list().unwrap().len(), 0);
+    backups.find(None).expect_err("There are no backups");
+}
+
+/// Tests that backups hold the contents at the time and only the newest are kept
+#[test]
+fn test_backup_retention() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    fs::write(file.path(), "").unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o600))
+        .unwrap();
+    let backups = Backups::new(file.path(), 3);
+    for i in 0..5 {
+        fs::write(file.path(), i.to_string()).unwrap();
+        create(&backups).unwrap().expect("Should create a backup");
+        std::thread::sleep(Duration::from_millis(5)); // Make sure the timestamps differ
+    }
+
+    let list = backups.list().unwrap();
+    assert_eq!(list.len(), 3);
+    let contents: Vec<String> = list
+        .iter()
+        .map(|x| fs::read_to_string(&x.path).unwrap())
+        .collect();
+    assert_eq!(contents, vec!["2", "3", "4"]);
+    let mode = fs::metadata(&list[0].path).unwrap().mode() & 0o777;
+    assert_eq!(mode, 0o600);
+
+    assert_eq!(backups.find(None).unwrap(), list[2]);
+    assert_eq!(backups.find(Some(&list[0].timestamp)).unwrap(), list[0]);
+    backups.find(Some("19700101")).expect_err("No such backup");
+}
+
+/// Tests that backups of different files in the same directory are kept apart
+#[test]
+fn test_backup_separate_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let alice = temp.child("alice");
+    let bob = temp.child("alice.bob");
+    alice.write_str("alice").unwrap();
+    bob.write_str("bob").unwrap();
+    create(&Backups::new(alice.path(), 3)).unwrap();
+    create(&Backups::new(bob.path(), 3)).unwrap();
+    assert_eq!(Backups::new(alice.path(), 3).list().unwrap().len(), 1);
+    assert_eq!(Backups::new(bob.path(), 3).list().unwrap().len(), 1);
+}
+
+/// Tests that a symbolic link is never followed, neither as the backup directory nor as a backup
+#[test]
+fn test_backup_refuses_symlinks() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let home = temp.child("home");
+    let elsewhere = temp.child("elsewhere");
+    home.create_dir_all().unwrap();
+    elsewhere.create_dir_all().unwrap();
+    let file = home.child("authorized_keys");
+    file.write_str("keys").unwrap();
+    std::os::unix::fs::symlink(elsewhere.path(), home.child(BACKUP_DIR).path())
+        .unwrap();
+    let backups = Backups::new(file.path(), 3);
+    create(&backups).expect_err("The backup directory is a symbolic link");
+    backups
+        .list()
+        .expect_err("The backup directory is a symbolic link");
+    assert_eq!(fs::read_dir(elsewhere.path()).unwrap().count(), 0);
+
+    fs::remove_file(home.child(BACKUP_DIR).path()).unwrap();
+    create(&backups).unwrap();
+    let link = home
+        .child(BACKUP_DIR)
+        .child("authorized_keys.20220601-142501.123");
+    std::os::unix::fs::symlink(elsewhere.path(), link.path()).unwrap();
+    assert_eq!(backups.list().unwrap().len(), 1);
+}
@@ -0,0 +1,94 @@
+use super::*;
+
+const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn";
+
+/// Tests that the diff shows the changed lines, and the fingerprint and source of each key
+#[test]
+fn test_diff() {
+    let key: AuthorizedKey = KEY.parse().unwrap();
+    let added = Provenance::new("https://github.com/bob.keys", None, &[])
+        .tag(vec![key]);
+    let removed: AuthorizedKey =
+        format!("{} alice@laptop", KEY).parse().unwrap();
+    let before = format!("# my keys\n{}\n", removed);
+    let after = format!("# my keys\n{}\n", added[0]);
+    let change = Change {
+        path: PathBuf::from("/home/bob/.ssh/authorized_keys"),
+        before,
+        after,
+        added,
+        removed: vec![removed],
+        conflicts: vec![],
+        removals: vec![],
+    };
+
+    let diff = change.diff();
+    let lines: Vec<&str> = diff.lines().collect();
+    assert_eq!(lines[0], "--- /home/bob/.ssh/authorized_keys");
+    assert_eq!(lines[1], "+++ /home/bob/.ssh/authorized_keys");
+    assert_eq!(lines[2], "@@ -1,2 +1,2 @@");
+    assert_eq!(lines[3], " # my keys");
+    assert_eq!(lines[4], format!("-{} alice@laptop", KEY));
+    assert!(lines[5].starts_with(&format!("+{} keysync provider=github", KEY)));
+    assert_eq!(
+        &lines[6..],
+        [
+            "+ 256 SHA256:xgyBGHaYQjXdZSUYT2bHWEz7P31pcaXkw/ycggq5Ikc ssh-ed25519 github:bob",
+            "- 256 SHA256:xgyBGHaYQjXdZSUYT2bHWEz7P31pcaXkw/ycggq5Ikc ssh-ed25519 local"
+        ]
+    );
+}
+
+/// Tests that nothing is shown when the file is unchanged
+#[test]
+fn test_diff_empty() {
+    let change = Change {
+        path: PathBuf::from("authorized_keys"),
+        before: String::from("# my keys\n"),
+        after: String::from("# my keys\n"),
+        added: vec![],
+        removed: vec![],
+        conflicts: vec![],
+        removals: vec![],
+    };
+    assert!(change.is_empty());
+    assert_eq!(change.diff(), "");
+}
+
+/// Tests that each line tidy removed is listed with its reason
+#[test]
+fn test_diff_removals() {
+    let key: AuthorizedKey = format!("{} laptop", KEY).parse().unwrap();
+    let change = Change {
+        path: PathBuf::from("authorized_keys"),
+        before: format!("{}\n{}\nssh-rsa AAAA broken\n", key, KEY),
+        after: format!("{}\n", key),
+        added: vec![],
+        removed: vec![],
+        conflicts: vec![],
+        removals: vec![
+            Removal {
+                reason: Reason::Duplicate,
+                line: 2,
+                text: String::from(KEY),
+                key: Some(KEY.parse().unwrap()),
+            },
+            Removal {
+                reason: Reason::Malformed,
+                line: 3,
+                text: String::from("ssh-rsa AAAA broken"),
+                key: None,
+            },
+        ],
+    };
+
+    let diff = change.diff();
+    let lines: Vec<&str> = diff.lines().collect();
+    assert_eq!(
+        &lines[lines.len() - 2..],
+        [
+            "- duplicate 256 SHA256:xgyBGHaYQjXdZSUYT2bHWEz7P31pcaXkw/ycggq5Ikc ssh-ed25519 local",
+            "- malformed line 3: ssh-rsa AAAA broken"
+        ]
+    );
+}
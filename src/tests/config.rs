@@ -0,0 +1,63 @@
+use super::*;
+use assert_fs::prelude::*;
+
+/// Tests that a missing config file gives the defaults
+#[test]
+fn test_missing_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = Config::load_path(temp.path().join("config.toml"))
+        .expect("Missing file should use defaults");
+    assert_eq!(config, Config::default());
+}
+
+/// Tests that settings are read and missing ones are defaulted
+#[test]
+fn test_load_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("config.toml");
+    file.write_str("backups = 3\ncentral-dir = \"/etc/ssh/keys.d\"\ncache-dir = \"/run/keysync\"\n")
+        .unwrap();
+    let config = Config::load_path(file.path()).unwrap();
+    assert_eq!(config.backups, 3);
+    assert_eq!(config.central_dir, PathBuf::from("/etc/ssh/keys.d"));
+    assert_eq!(config.cache_dir, PathBuf::from("/run/keysync"));
+
+    file.write_str("[policy]\ndeny = [\"ssh-dss\"]\nmin-rsa-bits = 3072\n")
+        .unwrap();
+    let config = Config::load_path(file.path()).unwrap();
+    assert_eq!(config.policy.to_string(), "deny=ssh-dss min-rsa-bits=3072");
+
+    file.write_str("on-conflict = \"refuse\"\nremove-missing-after = 3\n")
+        .unwrap();
+    let config = Config::load_path(file.path()).unwrap();
+    assert_eq!(config.on_conflict, OnConflict::Refuse);
+    assert_eq!(config.remove_missing_after, 3);
+
+    file.write_str("quarantine = \"2d\"\n").unwrap();
+    let config = Config::load_path(file.path()).unwrap();
+    assert_eq!(config.quarantine().unwrap(), Some(Duration::days(2)));
+    assert_eq!(Config::default().quarantine().unwrap(), None);
+
+    file.write_str("gitlab-hosts = [\"gitlab.example.com\"]\n")
+        .unwrap();
+    let config = Config::load_path(file.path()).unwrap();
+    assert_eq!(config.gitlab_hosts, vec!["gitlab.example.com"]);
+
+    file.write_str("").unwrap();
+    assert_eq!(Config::load_path(file.path()).unwrap(), Config::default());
+}
+
+/// Tests that unknown settings are an error, to catch typos
+#[test]
+fn test_bad_config() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("config.toml");
+    file.write_str("backup = 3\n").unwrap();
+    Config::load_path(file.path()).expect_err("Unknown setting");
+    file.write_str("[policy]\nmin-bits = 3072\n").unwrap();
+    Config::load_path(file.path()).expect_err("Unknown setting");
+    file.write_str("backups = \"three\"\n").unwrap();
+    Config::load_path(file.path()).expect_err("Wrong type");
+    file.write_str("quarantine = \"2 days\"\n").unwrap();
+    Config::load_path(file.path()).expect_err("Invalid duration");
+}
@@ -21,14 +21,22 @@ fn test_get_last_modified() {
 /// Tests that schedule only excepts good data
 #[test]
 fn test_new_schedule() {
-    Schedule::new(None, "budd", "foo", "bar")
+    Schedule::new(None, "budd", "foo", "bar", "", "home", "")
         .expect_err("Bad data should error");
-    Schedule::new(None, "budd", "foo", "https://github.com")
+    Schedule::new(None, "budd", "foo", "https://github.com", "", "home", "")
         .expect_err("Bad data should error");
-    Schedule::new(None, "budd", "@daily", "bar")
+    Schedule::new(None, "budd", "@daily", "bar", "", "home", "")
         .expect_err("Bad data should error");
-    Schedule::new(Some(1), "budd", "@daily", "https://github.com")
-        .expect("Data should pass");
+    Schedule::new(
+        Some(1),
+        "budd",
+        "@daily",
+        "https://github.com",
+        "",
+        "home",
+        "",
+    )
+    .expect("Data should pass");
 }
 
 /// Tests that the db file is properly created
@@ -54,13 +62,13 @@ fn test_add_schedule() {
     let db = Database::open_path(temp.path().join("file.db"))
         .expect("Should create the database file");
     assert!(db
-        .add_schedule("budd", "@daily", "https://github.com")
+        .add_schedule("budd", "@daily", "https://github.com", "", "home", "")
         .expect("No problems here"));
     assert!(!db
-        .add_schedule("budd", "@daily", "https://github.com")
+        .add_schedule("budd", "@daily", "https://github.com", "", "home", "")
         .expect("Duplicates! return false"));
     assert!(db
-        .add_schedule("budd", "@monthly", "https://github.com")
+        .add_schedule("budd", "@monthly", "https://github.com", "", "home", "")
         .expect("new data no problem"));
 }
 
@@ -71,16 +79,16 @@ fn test_get_schedule() {
     let db = Database::open_path(temp.path().join("file.db"))
         .expect("Should create the database file");
     assert!(db
-        .add_schedule("budd", "@daily", "https://github.com")
+        .add_schedule("budd", "@daily", "https://github.com", "", "home", "")
         .expect("No problems here"));
     assert!(db
-        .add_schedule("budd", "@monthly", "https://github.com")
+        .add_schedule("budd", "@monthly", "https://github.com", "", "home", "")
         .expect("No problem"));
 
     assert_eq!(db.get_schedules().unwrap().len(), 2);
 
     assert!(db
-        .add_schedule("budd", "@weekly", "https://github.com")
+        .add_schedule("budd", "@weekly", "https://github.com", "", "home", "")
         .expect("No problem"));
 
     assert_eq!(db.get_schedules().unwrap().len(), 3);
@@ -93,14 +101,14 @@ fn test_remove_schedule() {
     let db = Database::open_path(temp.path().join("file.db"))
         .expect("Should create the database file");
     assert!(db
-        .add_schedule("budd", "@daily", "https://github.com")
+        .add_schedule("budd", "@daily", "https://github.com", "", "home", "")
         .expect("No problems here"));
     assert!(db
-        .add_schedule("budd", "@monthly", "https://github.com")
+        .add_schedule("budd", "@monthly", "https://github.com", "", "home", "")
         .expect("No problem"));
 
     assert!(db
-        .add_schedule("budd", "@weekly", "https://github.com")
+        .add_schedule("budd", "@weekly", "https://github.com", "", "home", "")
         .expect("No problem"));
 
     assert_eq!(db.get_schedules().unwrap().len(), 3);
@@ -116,3 +124,235 @@ fn test_remove_schedule() {
     db.delete_schedule(0)
         .expect("Can't remove when there are none");
 }
+
+/// Tests that schedule only excepts valid sshd options
+#[test]
+fn test_new_schedule_options() {
+    Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "no-pty,",
+        "home",
+        "",
+    )
+    .expect_err("Bad grammar should error");
+    Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "no-such",
+        "home",
+        "",
+    )
+    .expect_err("Unknown option should error");
+    Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "from",
+        "home",
+        "",
+    )
+    .expect_err("Missing value should error");
+    let schedule = Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "restrict,from=\"10.0.0.0/8\"",
+        "home",
+        "",
+    )
+    .expect("Data should pass");
+    assert_eq!(schedule.key_options().unwrap().len(), 2);
+}
+
+/// Tests that options are stored, and that a database without the options column is upgraded
+#[test]
+fn test_schedule_options_migration() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let path = temp.path().join("file.db");
+    let conn = Connection::open(&path).unwrap();
+    conn.execute(
+        "create table Schedule (
+        id integer primary key,
+        user text not null,
+        cron text not null,
+        url text not null,
+        unique (user, cron, url)
+        )",
+        [],
+    )
+    .unwrap();
+    conn.execute(
+        "INSERT INTO Schedule (user, cron, url) VALUES ('budd', '@daily', 'https://github.com')",
+        [],
+    )
+    .unwrap();
+    drop(conn);
+
+    let db = Database::open_path(&path).expect("Should upgrade the database");
+    assert!(db
+        .add_schedule(
+            "budd",
+            "@weekly",
+            "https://github.com",
+            "no-pty",
+            "home",
+            ""
+        )
+        .expect("No problem"));
+    let schedules = db.get_schedules().unwrap();
+    assert_eq!(schedules.len(), 2);
+    assert_eq!(schedules[0].options, "");
+    assert_eq!(schedules[1].options, "no-pty");
+    assert_eq!(schedules[0].target, "home");
+}
+
+/// Tests that schedule only excepts home or an absolute central directory as the target
+#[test]
+fn test_new_schedule_target() {
+    Schedule::new(None, "budd", "@daily", "https://github.com", "", "keys", "")
+        .expect_err("Relative directory should error");
+    let schedule = Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "",
+        "/etc/keysync/keys",
+        "",
+    )
+    .expect("Data should pass");
+    assert_eq!(
+        schedule.key_target().unwrap(),
+        Target::Central(PathBuf::from("/etc/keysync/keys"))
+    );
+}
+
+/// Tests that schedule only excepts a valid policy spec
+#[test]
+fn test_new_schedule_policy() {
+    Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "",
+        "home",
+        "min-bits=2048",
+    )
+    .expect_err("Unknown rule should error");
+    let schedule = Schedule::new(
+        None,
+        "budd",
+        "@daily",
+        "https://github.com",
+        "",
+        "home",
+        "deny=ssh-dss",
+    )
+    .expect("Data should pass");
+    assert_eq!(schedule.key_policy().unwrap().deny, vec!["ssh-dss"]);
+}
+
+/// Tests that a job is for its user, or every member of its group
+#[test]
+fn test_schedule_users() {
+    let schedule = |user| {
+        Schedule::new(
+            None,
+            user,
+            "@daily",
+            "https://github.com",
+            "",
+            "home",
+            "",
+        )
+        .unwrap()
+    };
+    assert_eq!(schedule("budd").users().unwrap(), vec!["budd"]);
+    assert!(!schedule("%root")
+        .users()
+        .unwrap()
+        .contains(&String::from("root")));
+    schedule("%keysync-no-such-group")
+        .users()
+        .expect_err("Unknown group");
+}
+
+/// Tests that a url is pinned to the ID of its remote account, and can be pinned again
+#[test]
+fn test_pin_account() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let db = Database::open_path(temp.path().join("file.db"))
+        .expect("Should create the database file");
+    let url = "https://github.com/budde25.keys";
+    assert_eq!(db.pinned_account(url).unwrap(), None);
+    db.pin_account(url, "1234").unwrap();
+    assert_eq!(db.pinned_account(url).unwrap().unwrap(), "1234");
+    db.pin_account(url, "5678").unwrap();
+    assert_eq!(db.pinned_account(url).unwrap().unwrap(), "5678");
+}
+
+/// Tests that the users apply wrote keys for are recorded and forgotten
+#[test]
+fn test_mapped_users() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let db = Database::open_path(temp.path().join("file.db"))
+        .expect("Should create the database file");
+    assert!(db.mapped_users().unwrap().is_empty());
+    db.add_mapped_user("deploy").unwrap();
+    db.add_mapped_user("budd").unwrap();
+    db.add_mapped_user("deploy").unwrap();
+    assert_eq!(db.mapped_users().unwrap(), ["budd", "deploy"]);
+    db.remove_mapped_user("deploy").unwrap();
+    assert_eq!(db.mapped_users().unwrap(), ["budd"]);
+}
+
+/// Tests that the missing count of a job is kept
+#[test]
+fn test_missing_count() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let db = Database::open_path(temp.path().join("file.db"))
+        .expect("Should create the database file");
+    assert!(db
+        .add_schedule("budd", "@daily", "https://github.com", "", "home", "")
+        .expect("No problems here"));
+    let id = db.get_schedules().unwrap()[0].id.unwrap();
+    assert_eq!(db.get_schedules().unwrap()[0].missing_count, 0);
+
+    db.set_missing_count(id, 2).unwrap();
+    assert_eq!(db.get_schedules().unwrap()[0].missing_count, 2);
+}
+
+/// Tests that the time a job was first served a key is kept until it is no longer served
+#[test]
+fn test_pending_keys() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let db = Database::open_path(temp.path().join("file.db"))
+        .expect("Should create the database file");
+    let first = DateTime::parse_from_rfc3339("2022-06-01T14:25:01Z")
+        .unwrap()
+        .with_timezone(&Utc);
+    let later = DateTime::parse_from_rfc3339("2022-06-02T08:00:00Z")
+        .unwrap()
+        .with_timezone(&Utc);
+
+    assert_eq!(db.key_since(1, "SHA256:a", first).unwrap(), first);
+    assert_eq!(db.key_since(1, "SHA256:a", later).unwrap(), first);
+    assert_eq!(db.key_since(1, "SHA256:b", later).unwrap(), later);
+    assert_eq!(db.key_since(2, "SHA256:a", later).unwrap(), later);
+    assert_eq!(db.get_pending().unwrap().len(), 3);
+
+    db.forget_keys(1, &[String::from("SHA256:b")]).unwrap();
+    assert_eq!(db.key_since(1, "SHA256:a", later).unwrap(), later);
+    assert_eq!(db.get_pending().unwrap().len(), 3);
+
+    db.delete_schedule(2).unwrap();
+    assert!(db.get_pending().unwrap().iter().all(|x| x.job == 1));
+}
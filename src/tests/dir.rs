@@ -0,0 +1,62 @@
+use super::*;
+use assert_fs::prelude::*;
+
+/// Tests that files are created, read and renamed relative to the open directory
+#[test]
+fn test_dir_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let mut dir = Dir::open(temp.path(), None).unwrap().unwrap();
+    let file = dir.create_file("new", 0o600).unwrap();
+    file.write_all(b"keys").unwrap();
+    assert_eq!(dir.create_file("new", 0o600).unwrap_err(), Errno::EEXIST);
+    dir.rename("new", "authorized_keys").unwrap();
+    let content = dir.open_file("authorized_keys").unwrap().unwrap();
+    assert_eq!(content.read_to_end().unwrap(), b"keys");
+    assert!(dir.open_file("new").unwrap().is_none());
+    assert_eq!(dir.files().unwrap(), ["authorized_keys"]);
+    dir.remove("authorized_keys").unwrap();
+    assert!(dir.stat("authorized_keys").unwrap().is_none());
+}
+
+/// Tests that symbolic links are refused below the trusted directory, and the trusted directory itself may be one
+#[test]
+fn test_dir_symlinks() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let elsewhere = temp.child("elsewhere");
+    elsewhere.create_dir_all().unwrap();
+    elsewhere.child("keys").write_str("secret").unwrap();
+    let home = temp.child("home");
+    home.create_dir_all().unwrap();
+    std::os::unix::fs::symlink(elsewhere.path(), home.child(".ssh").path())
+        .unwrap();
+    let linked_home = temp.child("linked");
+    std::os::unix::fs::symlink(home.path(), linked_home.path()).unwrap();
+
+    Dir::open(&linked_home.path().join(".ssh"), Some(linked_home.path()))
+        .expect_err("The symbolic link is refused");
+    Dir::open(&home.path().join(".ssh"), None)
+        .expect_err("The directory itself is not followed");
+    let dir = Dir::open(elsewhere.path(), None).unwrap().unwrap();
+    std::os::unix::fs::symlink("keys", elsewhere.child("link").path()).unwrap();
+    dir.open_file("link").expect_err("A linked file is refused");
+    assert_eq!(dir.create_file("link", 0o600).unwrap_err(), Errno::EEXIST);
+    elsewhere.child("keys").assert("secret");
+}
+
+/// Tests that missing directories below the trusted directory are created, and missing ones are not found otherwise
+#[test]
+fn test_dir_create() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let path = temp.path().join(".config/ssh");
+    assert!(Dir::open(&path, Some(temp.path())).unwrap().is_none());
+    let owner = (Uid::effective(), Gid::effective());
+    Dir::create(&path, Some(temp.path()), (owner, 0o700)).unwrap();
+    let mode = fs::metadata(&path).unwrap().permissions();
+    assert_eq!(
+        std::os::unix::fs::PermissionsExt::mode(&mode) & 0o777,
+        0o700
+    );
+    assert!(Dir::open(&path, Some(temp.path())).unwrap().is_some());
+    Dir::open(&temp.path().join("../x"), Some(temp.path()))
+        .expect_err("Leaving the trusted directory is refused");
+}
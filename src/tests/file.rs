@@ -1,5 +1,12 @@
 use super::*;
+use crate::provenance::{Provenance, Source};
 use assert_fs::prelude::*;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+/// Parses test key strings into keys
+fn parse_keys(keys: Vec<String>) -> Vec<AuthorizedKey> {
+    keys.iter().map(|x| x.parse().unwrap()).collect()
+}
 
 /// Tests that a new file will have no keys
 #[test]
@@ -7,7 +14,7 @@ fn test_getting_empty_keys() {
     let temp = assert_fs::TempDir::new().unwrap();
     let file = temp.child("authorized_keys"); // Don't create, functions should do it for us
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 0);
+    assert_eq!(authorized_keys.keys().unwrap().len(), 0);
 }
 
 /// Tests that a new file will have no keys
@@ -19,7 +26,7 @@ fn test_getting_two_keys() {
     file.write_str("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDArNszFqR3vxzTe+pr/U/kmCn8aQAHNKfPMK4DJEvMvEbypiJV3Pm4iQG8jK6xBOTvcrFJTDX0VvgG0ky+iGOaLXw/M30BUsRhZlonasa0tbuu1PtHXlToXaCPyIPB39XucTjOQYtyFoS7yMfBuw0JhQ4ETJflvvHet5UkrbcqoSrac2ljtokmwR7z6cFEJTDXncEAhJsSJVQgPXWlf/j76XV8tP7ZFOBR7UVLSR2TXCLtg67o4Whu3ji/BV5Qa6t6Ef6rT4mndB29rY9D35qpASVlic84WzYKwRSfsc9FtryaA6mQMbfhN3xySKkfV5CgrVCH/rHGP09VzMlrlR+tHZDqznxeL4pr7+uJOHvMbgZHBvdbanQyApSGdB6HbRB1z8lVmbtOAsuK4TNkTQUNo8204NKJgtEsZnbqOWM0OMiJpjmhftqMq0Wl7OzZYWDzAEgS3ELoAl1DCkO4RkXsXWdHNK3p2MtxXOj3yM6MWZTPGT3dJXqATdu4lzknvSc= #maybe a comment
         ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDPx4jsUuivW/Yz0r7eD/InptzObq+qmwEP7fJrNZIOkKYyfVaxIxHYnAix7h4Qjk6dRq15to9slBSohRlXpXAx0WFpOMRgxC56uqnbGfh3fh8XxEIr23OHxiwoh4paS6CKu9Jz53S8lM6jSHsdH+0CmLm/iEw9Y0KtzOEzee6RR6EJUvs4TGSvaapOQJse4ZQNFJU0xBMVaGs4HQ2VitwrWVn/lvJoSoWk2fAAEGLcI2FOEoMBfnaAwyRj3F/L3hJ4vu77N7qvxdVCz7FRAEGPBcnoaeB4ivA2MXz3tEkHAilMTiUIMdPjS65lPyXfzWvlVQid3iMOb7oQcD4cI3oJ").unwrap();
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 2);
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
 }
 
 /// Tests that we can write to an existing file
@@ -29,8 +36,15 @@ fn test_writing_empty_file() {
     let file = temp.child("authorized_keys");
     let keys = vec!["ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDArNszFqR3vxzTe+pr/U/kmCn8aQAHNKfPMK4DJEvMvEbypiJV3Pm4iQG8jK6xBOTvcrFJTDX0VvgG0ky+iGOaLXw/M30BUsRhZlonasa0tbuu1PtHXlToXaCPyIPB39XucTjOQYtyFoS7yMfBuw0JhQ4ETJflvvHet5UkrbcqoSrac2ljtokmwR7z6cFEJTDXncEAhJsSJVQgPXWlf/j76XV8tP7ZFOBR7UVLSR2TXCLtg67o4Whu3ji/BV5Qa6t6Ef6rT4mndB29rY9D35qpASVlic84WzYKwRSfsc9FtryaA6mQMbfhN3xySKkfV5CgrVCH/rHGP09VzMlrlR+tHZDqznxeL4pr7+uJOHvMbgZHBvdbanQyApSGdB6HbRB1z8lVmbtOAsuK4TNkTQUNo8204NKJgtEsZnbqOWM0OMiJpjmhftqMq0Wl7OzZYWDzAEgS3ELoAl1DCkO4RkXsXWdHNK3p2MtxXOj3yM6MWZTPGT3dJXqATdu4lzknvSc=".to_owned(),"ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDPx4jsUuivW/Yz0r7eD/InptzObq+qmwEP7fJrNZIOkKYyfVaxIxHYnAix7h4Qjk6dRq15to9slBSohRlXpXAx0WFpOMRgxC56uqnbGfh3fh8XxEIr23OHxiwoh4paS6CKu9Jz53S8lM6jSHsdH+0CmLm/iEw9Y0KtzOEzee6RR6EJUvs4TGSvaapOQJse4ZQNFJU0xBMVaGs4HQ2VitwrWVn/lvJoSoWk2fAAEGLcI2FOEoMBfnaAwyRj3F/L3hJ4vu77N7qvxdVCz7FRAEGPBcnoaeB4ivA2MXz3tEkHAilMTiUIMdPjS65lPyXfzWvlVQid3iMOb7oQcD4cI3oJ".to_owned()];
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.write_keys(keys, false).unwrap(), 2);
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 2);
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &[], false)
+            .unwrap()
+            .added
+            .len(),
+        2
+    );
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
 }
 
 /// Tests that we can write to an existing file with data
@@ -42,8 +56,15 @@ fn test_writing_empty_file_one_dup() {
         ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDPx4jsUuivW/Yz0r7eD/InptzObq+qmwEP7fJrNZIOkKYyfVaxIxHYnAix7h4Qjk6dRq15to9slBSohRlXpXAx0WFpOMRgxC56uqnbGfh3fh8XxEIr23OHxiwoh4paS6CKu9Jz53S8lM6jSHsdH+0CmLm/iEw9Y0KtzOEzee6RR6EJUvs4TGSvaapOQJse4ZQNFJU0xBMVaGs4HQ2VitwrWVn/lvJoSoWk2fAAEGLcI2FOEoMBfnaAwyRj3F/L3hJ4vu77N7qvxdVCz7FRAEGPBcnoaeB4ivA2MXz3tEkHAilMTiUIMdPjS65lPyXfzWvlVQid3iMOb7oQcD4cI3oJ\n").unwrap();
     let keys = vec!["ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDNYBebNl8QY24FWJZLmwDrtSvQZaiMXe8sNUHbDNkPPNKe1XPqH1tD8ZBjqxlz/mxMfLOR6izFYj6Y6OSo4kqmuhhXZvSST0sPashF7PM/sfiR4gjoisuE/CfUsjGbvSBfQ5nfUJkQoUrzMQjA5CltbGO8prd6mt0u37RJqdBzAUAKaflwlpei5AWnE3a0RAlm29s/1bec28oQWkxqjLf11SqqJNgOsL70NDByMk+8LQ2OqEJHVHAIQjml/oSgKBG+SLO3uOX6M3bhmtAaP2MYAZdp/84qDFMX5lKk37k3xMjWmlHstyqkgJ3Hfp/Ft2KC1pPTKNK+C5VYqlROO/qKNSlIf80HNSGHBJymU/ncjcxOaofSkrLHzANsloYJileuUZs9sN48Eebrx0enz5Bf6ySxp5mmjesbASd0nxHXjZ0k9fiacAVeUTs54H/1VfIhLfVz4L8laZ79Ncbi0rprK7TofAKIErOOddgp3Wlj6CKeF+v6yPRCimb+IUt0qf0=".to_owned(),"ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDPx4jsUuivW/Yz0r7eD/InptzObq+qmwEP7fJrNZIOkKYyfVaxIxHYnAix7h4Qjk6dRq15to9slBSohRlXpXAx0WFpOMRgxC56uqnbGfh3fh8XxEIr23OHxiwoh4paS6CKu9Jz53S8lM6jSHsdH+0CmLm/iEw9Y0KtzOEzee6RR6EJUvs4TGSvaapOQJse4ZQNFJU0xBMVaGs4HQ2VitwrWVn/lvJoSoWk2fAAEGLcI2FOEoMBfnaAwyRj3F/L3hJ4vu77N7qvxdVCz7FRAEGPBcnoaeB4ivA2MXz3tEkHAilMTiUIMdPjS65lPyXfzWvlVQid3iMOb7oQcD4cI3oJ".to_owned()];
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.write_keys(keys, false).unwrap(), 1); // wrote one key only, since one was already there
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 3);
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &[], false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    ); // wrote one key only, since one was already there
+    assert_eq!(authorized_keys.keys().unwrap().len(), 3);
 }
 
 /// Tests that we can write to an existing file with data
@@ -54,8 +75,15 @@ fn test_writing_existing_with_a_newline() {
     file.write_str("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDArNszFqR3vxzTe+pr/U/kmCn8aQAHNKfPMK4DJEvMvEbypiJV3Pm4iQG8jK6xBOTvcrFJTDX0VvgG0ky+iGOaLXw/M30BUsRhZlonasa0tbuu1PtHXlToXaCPyIPB39XucTjOQYtyFoS7yMfBuw0JhQ4ETJflvvHet5UkrbcqoSrac2ljtokmwR7z6cFEJTDXncEAhJsSJVQgPXWlf/j76XV8tP7ZFOBR7UVLSR2TXCLtg67o4Whu3ji/BV5Qa6t6Ef6rT4mndB29rY9D35qpASVlic84WzYKwRSfsc9FtryaA6mQMbfhN3xySKkfV5CgrVCH/rHGP09VzMlrlR+tHZDqznxeL4pr7+uJOHvMbgZHBvdbanQyApSGdB6HbRB1z8lVmbtOAsuK4TNkTQUNo8204NKJgtEsZnbqOWM0OMiJpjmhftqMq0Wl7OzZYWDzAEgS3ELoAl1DCkO4RkXsXWdHNK3p2MtxXOj3yM6MWZTPGT3dJXqATdu4lzknvSc= #maybe a comment\n").unwrap();
     let keys = vec!["ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDNYBebNl8QY24FWJZLmwDrtSvQZaiMXe8sNUHbDNkPPNKe1XPqH1tD8ZBjqxlz/mxMfLOR6izFYj6Y6OSo4kqmuhhXZvSST0sPashF7PM/sfiR4gjoisuE/CfUsjGbvSBfQ5nfUJkQoUrzMQjA5CltbGO8prd6mt0u37RJqdBzAUAKaflwlpei5AWnE3a0RAlm29s/1bec28oQWkxqjLf11SqqJNgOsL70NDByMk+8LQ2OqEJHVHAIQjml/oSgKBG+SLO3uOX6M3bhmtAaP2MYAZdp/84qDFMX5lKk37k3xMjWmlHstyqkgJ3Hfp/Ft2KC1pPTKNK+C5VYqlROO/qKNSlIf80HNSGHBJymU/ncjcxOaofSkrLHzANsloYJileuUZs9sN48Eebrx0enz5Bf6ySxp5mmjesbASd0nxHXjZ0k9fiacAVeUTs54H/1VfIhLfVz4L8laZ79Ncbi0rprK7TofAKIErOOddgp3Wlj6CKeF+v6yPRCimb+IUt0qf0=".to_owned()];
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.write_keys(keys, false).unwrap(), 1);
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 2);
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &[], false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    );
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
 }
 
 /// Tests that we can write to an existing file with data
@@ -66,6 +94,446 @@ fn test_writing_existing_without_a_newline() {
     file.write_str("ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDArNszFqR3vxzTe+pr/U/kmCn8aQAHNKfPMK4DJEvMvEbypiJV3Pm4iQG8jK6xBOTvcrFJTDX0VvgG0ky+iGOaLXw/M30BUsRhZlonasa0tbuu1PtHXlToXaCPyIPB39XucTjOQYtyFoS7yMfBuw0JhQ4ETJflvvHet5UkrbcqoSrac2ljtokmwR7z6cFEJTDXncEAhJsSJVQgPXWlf/j76XV8tP7ZFOBR7UVLSR2TXCLtg67o4Whu3ji/BV5Qa6t6Ef6rT4mndB29rY9D35qpASVlic84WzYKwRSfsc9FtryaA6mQMbfhN3xySKkfV5CgrVCH/rHGP09VzMlrlR+tHZDqznxeL4pr7+uJOHvMbgZHBvdbanQyApSGdB6HbRB1z8lVmbtOAsuK4TNkTQUNo8204NKJgtEsZnbqOWM0OMiJpjmhftqMq0Wl7OzZYWDzAEgS3ELoAl1DCkO4RkXsXWdHNK3p2MtxXOj3yM6MWZTPGT3dJXqATdu4lzknvSc= #maybe a comment").unwrap();
     let keys = vec!["ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDNYBebNl8QY24FWJZLmwDrtSvQZaiMXe8sNUHbDNkPPNKe1XPqH1tD8ZBjqxlz/mxMfLOR6izFYj6Y6OSo4kqmuhhXZvSST0sPashF7PM/sfiR4gjoisuE/CfUsjGbvSBfQ5nfUJkQoUrzMQjA5CltbGO8prd6mt0u37RJqdBzAUAKaflwlpei5AWnE3a0RAlm29s/1bec28oQWkxqjLf11SqqJNgOsL70NDByMk+8LQ2OqEJHVHAIQjml/oSgKBG+SLO3uOX6M3bhmtAaP2MYAZdp/84qDFMX5lKk37k3xMjWmlHstyqkgJ3Hfp/Ft2KC1pPTKNK+C5VYqlROO/qKNSlIf80HNSGHBJymU/ncjcxOaofSkrLHzANsloYJileuUZs9sN48Eebrx0enz5Bf6ySxp5mmjesbASd0nxHXjZ0k9fiacAVeUTs54H/1VfIhLfVz4L8laZ79Ncbi0rprK7TofAKIErOOddgp3Wlj6CKeF+v6yPRCimb+IUt0qf0=".to_owned()];
     let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
-    assert_eq!(authorized_keys.write_keys(keys, false).unwrap(), 1);
-    assert_eq!(authorized_keys.get_keys().unwrap().0.len(), 2);
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &[], false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    );
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
+}
+
+/// Tests that options are prepended to the written keys
+#[test]
+fn test_writing_with_options() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let keys = vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io".to_owned()];
+    let options = keys::parse_options("restrict,from=\"10.0.0.0/8\"").unwrap();
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &options, false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    );
+    file.assert("restrict,from=\"10.0.0.0/8\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\n");
+    assert_eq!(authorized_keys.keys().unwrap()[0].options, options);
+}
+
+/// Tests that writing replaces the file while keeping its mode
+#[test]
+fn test_writing_keeps_mode() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("# keep me\n").unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o600))
+        .unwrap();
+    let keys = vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io".to_owned()];
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    assert_eq!(
+        authorized_keys
+            .write_keys(parse_keys(keys), &[], false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    );
+    let mode = fs::metadata(file.path()).unwrap().mode() & 0o777;
+    assert_eq!(mode, 0o600);
+    assert!(authorized_keys.read().unwrap().starts_with("# keep me\n"));
+    // No temporary files should be left behind
+    assert!(!fs::read_dir(temp.path()).unwrap().any(|x| x
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .ends_with("tmp")));
+}
+
+/// Tests that a file which does not exist reads as having no keys
+#[test]
+fn test_reading_missing_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    fs::remove_file(file.path()).unwrap();
+    assert_eq!(authorized_keys.keys().unwrap().len(), 0);
+    assert!(!file.path().exists());
+}
+
+/// Tests that replacing the keys of a job removes the ones it no longer serves, leaving other keys alone
+#[test]
+fn test_replacing_job_keys() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    let first = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn bob@laptop")]);
+    let second = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf bob@desktop")]);
+    let gitlab = [String::from("gitlab.com")];
+    let github = Provenance::new("https://github.com/bob.keys", Some(1), &[]);
+    let other =
+        Provenance::new("https://gitlab.com/bob.keys", Some(2), &gitlab);
+    authorized_keys
+        .write_keys(other.tag(second.clone()), &[], false)
+        .unwrap();
+    authorized_keys
+        .replace_job_keys(1, github.tag(first), &[], false)
+        .unwrap();
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
+
+    let change = authorized_keys
+        .replace_job_keys(1, github.tag(second), &[], false)
+        .unwrap();
+    assert_eq!(change.removed.len(), 1);
+    let keys = authorized_keys.keys().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(provenance::is_from_job(&keys[0], 2));
+}
+
+/// Tests that replacing the mapped keys removes the keys of an identity dropped from the mapping, leaving keys imported otherwise alone
+#[test]
+fn test_replacing_mapped_keys() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    let alice = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn alice@laptop")]);
+    let asmith = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf asmith@desktop")]);
+    let carol = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIC0g0z2T7eEskrJtl5VxuZcy69tdtKIAvTWmeloVRqEz carol@io")]);
+    let alice = provenance::mark_mapped(
+        Provenance::new("https://github.com/alice.keys", None, &[]).tag(alice),
+    );
+    let asmith = provenance::mark_mapped(
+        Provenance::new("https://launchpad.net/~asmith/+sshkeys", None, &[])
+            .tag(asmith),
+    );
+    authorized_keys
+        .write_keys(
+            Provenance::new("https://github.com/carol.keys", None, &[])
+                .tag(carol),
+            &[],
+            false,
+        )
+        .unwrap();
+    let mut both = alice.clone();
+    both.extend(asmith);
+    authorized_keys.replace_mapped_keys(both, false).unwrap();
+    assert_eq!(authorized_keys.keys().unwrap().len(), 3);
+
+    // lp:asmith is dropped from the mapping
+    let change = authorized_keys.replace_mapped_keys(alice, false).unwrap();
+    assert_eq!(change.removed.len(), 1);
+    assert!(Source::from_str("lp:asmith")
+        .unwrap()
+        .matches(&change.removed[0]));
+    let keys = authorized_keys.keys().unwrap();
+    assert_eq!(keys.len(), 2);
+    assert!(keys.iter().all(|x| !x
+        .comment
+        .as_ref()
+        .unwrap()
+        .contains("asmith")));
+
+    // The whole entry is dropped
+    let change = authorized_keys.replace_mapped_keys(vec![], false).unwrap();
+    assert_eq!(change.removed.len(), 1);
+    let keys = authorized_keys.keys().unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(!provenance::is_mapped(&keys[0]));
+}
+
+/// Tests that a symbolic link is not written through, so root cannot be made to overwrite the file it points at
+#[test]
+fn test_writing_refuses_symlink() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let target = temp.child("passwd");
+    target.write_str("root:x:0:0::/root:/bin/sh\n").unwrap();
+    let file = temp.child("authorized_keys");
+    std::os::unix::fs::symlink(target.path(), file.path()).unwrap();
+    let keys = vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io".to_owned()];
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    authorized_keys
+        .write_keys(parse_keys(keys), &[], false)
+        .expect_err("Symbolic links are refused");
+    target.assert("root:x:0:0::/root:/bin/sh\n");
+}
+
+/// Tests that concurrent writers serialize and do not duplicate keys
+#[test]
+fn test_writing_concurrently() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.touch().unwrap();
+    let handles: Vec<std::thread::JoinHandle<usize>> = (0..8)
+        .map(|_| {
+            let path = file.path().to_owned();
+            std::thread::spawn(move || {
+                let keys = vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io".to_owned()];
+                let authorized_keys = AuthorizedKeys::open_path(path).unwrap();
+                authorized_keys
+                    .write_keys(parse_keys(keys), &[], false)
+                    .unwrap().added.len()
+            })
+        })
+        .collect();
+    let written: usize = handles.into_iter().map(|x| x.join().unwrap()).sum();
+    assert_eq!(written, 1);
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    assert_eq!(authorized_keys.keys().unwrap().len(), 1);
+}
+
+/// Tests that a lock held elsewhere times out instead of blocking forever
+#[test]
+fn test_lock_timeout() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let authorized_keys =
+        AuthorizedKeys::open_path(temp.child("authorized_keys").path())
+            .unwrap();
+    let _held = authorized_keys.lock().unwrap();
+    let file = File::open(temp.path()).unwrap();
+    let timeout = std::time::Duration::from_millis(300);
+    let error = lock_exclusive(file, temp.path(), timeout).err().unwrap();
+    assert!(error.to_string().contains("Timed out"));
+}
+
+/// Tests that every write is backed up and can be rolled back
+#[test]
+fn test_writing_backup_restore() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("# original\n").unwrap();
+    let keys = vec!["ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io".to_owned()];
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    authorized_keys
+        .write_keys(parse_keys(keys), &[], false)
+        .unwrap()
+        .added
+        .len();
+    assert_eq!(authorized_keys.keys().unwrap().len(), 1);
+
+    let backup = authorized_keys.backups().find(None).unwrap();
+    authorized_keys.restore(&backup).unwrap();
+    file.assert("# original\n");
+    // The rollback itself can be undone
+    assert_eq!(authorized_keys.backups().list().unwrap().len(), 2);
+}
+
+/// Tests that only the selected keys are removed, keeping every other line and a backup
+#[test]
+fn test_remove_keys() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("# my keys\n\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=github user=bob\n").unwrap();
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+
+    let change = authorized_keys.remove_keys(|i, _| i == 2, true).unwrap();
+    assert_eq!(change.removed.len(), 1);
+    assert!(change.diff().contains("\n-ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=github user=bob\n"));
+    assert_eq!(authorized_keys.keys().unwrap().len(), 2);
+    assert!(authorized_keys.backups().list().unwrap().is_empty());
+
+    let source: Source = "gh:bob".parse().unwrap();
+    let change = authorized_keys
+        .remove_keys(|_, key| source.matches(key), false)
+        .unwrap();
+    assert_eq!(change.removed.len(), 1);
+    file.assert("# my keys\n\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\n");
+    assert_eq!(authorized_keys.backups().list().unwrap().len(), 1);
+
+    let change = authorized_keys
+        .remove_keys(|_, key| source.matches(key), false)
+        .unwrap();
+    assert!(change.is_empty());
+}
+
+/// Tests that the target is parsed from and stored as text
+#[test]
+fn test_target() {
+    assert_eq!(Target::from_str("home").unwrap(), Target::Home);
+    let central = Target::from_str("/etc/keysync/keys").unwrap();
+    assert_eq!(central, Target::Central(PathBuf::from("/etc/keysync/keys")));
+    assert_eq!(central.to_string(), "/etc/keysync/keys");
+    Target::from_str("keys").expect_err("Relative directory should error");
+
+    let trusted_ca = Target::from_str("trusted-ca:/etc/ssh/ca.pub").unwrap();
+    assert_eq!(
+        trusted_ca,
+        Target::TrustedCa(PathBuf::from("/etc/ssh/ca.pub"))
+    );
+    assert_eq!(trusted_ca.to_string(), "trusted-ca:/etc/ssh/ca.pub");
+    Target::from_str("trusted-ca:ca.pub")
+        .expect_err("Relative file should error");
+
+    let cache = Target::from_str("cache:/var/cache/keysync").unwrap();
+    assert_eq!(cache, Target::Cache(PathBuf::from("/var/cache/keysync")));
+    assert_eq!(cache.to_string(), "cache:/var/cache/keysync");
+    Target::from_str("cache:keysync")
+        .expect_err("Relative directory should error");
+}
+
+/// Tests that a central key file is created per user, root owned and readable by sshd
+#[test]
+fn test_open_central() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let dir = temp.path().join("keys");
+    let config = Config {
+        sshd_config: temp.path().join("sshd_config"),
+        central_dir: dir.clone(),
+        ..Config::default()
+    };
+    let authorized_keys = AuthorizedKeys::open(
+        Some("root"),
+        &Target::Central(dir.clone()),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(authorized_keys.path, dir.join("root"));
+
+    let metadata = fs::metadata(&authorized_keys.path).unwrap();
+    assert_eq!(metadata.mode() & 0o777, 0o644);
+    assert_eq!(metadata.uid(), 0);
+    assert_eq!(fs::metadata(&dir).unwrap().mode() & 0o777, 0o755);
+
+    let keys = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn")]);
+    assert_eq!(
+        authorized_keys
+            .write_keys(keys, &[], false)
+            .unwrap()
+            .added
+            .len(),
+        1
+    );
+    let metadata = fs::metadata(&authorized_keys.path).unwrap();
+    assert_eq!(metadata.mode() & 0o777, 0o644);
+}
+
+/// Tests that the CA keys file is created root owned and readable, without changing its directory
+#[test]
+fn test_open_trusted_ca() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.path().join("trusted_user_ca_keys");
+    let config = Config {
+        sshd_config: temp.path().join("sshd_config"),
+        ..Config::default()
+    };
+    let mode = fs::metadata(temp.path()).unwrap().mode();
+    let authorized_keys = AuthorizedKeys::open(
+        Some("root"),
+        &Target::TrustedCa(file.clone()),
+        &config,
+    )
+    .unwrap();
+    assert_eq!(authorized_keys.path, file);
+
+    let metadata = fs::metadata(&file).unwrap();
+    assert_eq!(metadata.mode() & 0o777, 0o644);
+    assert_eq!(metadata.uid(), 0);
+    assert_eq!(fs::metadata(temp.path()).unwrap().mode(), mode);
+
+    // sshd does not accept options in the file, not even an expiry from the provider
+    let mut keys = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn ca")]);
+    keys[0].options = keys::parse_options("expiry-time=\"29990101\"").unwrap();
+    authorized_keys.write_keys(keys, &[], false).unwrap();
+    assert_eq!(fs::read_to_string(&file).unwrap(), "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn ca\n");
+}
+
+/// Tests that writing removes expired managed keys, keeps the users own, and does not add keys that already expired
+#[test]
+fn test_writing_purges_expired() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("expiry-time=\"20200101\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=github user=bob\nexpiry-time=\"20200101\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\n").unwrap();
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+
+    let expired = keys::parse_options("expiry-time=\"20200101\"").unwrap();
+    let keys = parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=github user=bob")]);
+    let change = authorized_keys.write_keys(keys, &expired, false).unwrap();
+    assert!(change.added.is_empty());
+    assert_eq!(change.removed.len(), 1);
+    file.assert("expiry-time=\"20200101\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\n");
+}
+
+/// Tests that the earliest of the provider expiry and the options expiry is written, and keys the provider says expired are removed
+#[test]
+fn test_writing_provider_expiry() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=gitlab user=bob\n").unwrap();
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+
+    let mut keys = parse_keys(vec![
+        String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=gitlab user=bob"),
+        String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf keysync provider=gitlab user=bob"),
+    ]);
+    let early = keys::parse_timespec("209901010000").unwrap();
+    keys[0].set_expiry(keys::parse_timespec("20200101").unwrap());
+    keys[1].set_expiry(early);
+    let options =
+        keys::parse_options("no-pty,expiry-time=\"21000101\"").unwrap();
+
+    let change = authorized_keys.write_keys(keys, &options, false).unwrap();
+    assert_eq!(change.removed.len(), 1);
+    assert_eq!(change.added.len(), 1);
+    assert_eq!(change.added[0].options[0].name, "no-pty");
+    assert_eq!(change.added[0].expires(), Some(early));
+    assert_eq!(authorized_keys.keys().unwrap(), change.added);
+}
+
+/// Tests that tidying drops later copies of a key with the same options, expired imported keys, malformed lines and extra whitespace, keeping comments, options and copies with other options
+#[test]
+fn test_tidy() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    file.write_str("\n# my keys  \nno-pty  ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn laptop\n\n\n\nno-pty ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn laptop\nno-pty ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn #ssh-import keysync\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn desktop\nexpiry-time=\"20200101\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf keysync provider=github user=bob\nssh-rsa AAAA broken\n  ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io  \n\n").unwrap();
+    let authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+
+    let change = authorized_keys.tidy(true).unwrap();
+    let reasons: Vec<(Reason, usize)> =
+        change.removals.iter().map(|x| (x.reason, x.line)).collect();
+    assert_eq!(
+        reasons,
+        [
+            (Reason::Duplicate, 7),
+            (Reason::Duplicate, 8),
+            (Reason::Expired, 10),
+            (Reason::Malformed, 11)
+        ]
+    );
+    assert!(authorized_keys.backups().list().unwrap().is_empty());
+
+    authorized_keys.tidy(false).unwrap();
+    file.assert("# my keys\nno-pty ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn laptop\n\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn desktop\nssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf budd@io\n");
+    assert!(authorized_keys.tidy(false).unwrap().is_empty());
+}
+
+/// Tests that a key already in the file with other options is reported and kept, or refused
+#[test]
+fn test_writing_conflicts() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("authorized_keys");
+    let existing = "from=\"10.0.0.0/8\",command=\"backup\" ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn backup\n";
+    file.write_str(existing).unwrap();
+    let mut authorized_keys = AuthorizedKeys::open_path(file.path()).unwrap();
+    let keys = || {
+        parse_keys(vec![String::from("ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn keysync provider=github user=bob")])
+    };
+
+    let change = authorized_keys.write_keys(keys(), &[], false).unwrap();
+    assert!(change.is_empty());
+    assert_eq!(change.conflicts.len(), 1);
+    assert!(change.diff().contains("existing options 'from=\"10.0.0.0/8\",command=\"backup\"' differ from imported options ''"));
+
+    let same =
+        keys::parse_options("command=\"backup\",FROM=\"10.0.0.0/8\"").unwrap();
+    let change = authorized_keys.write_keys(keys(), &same, false).unwrap();
+    assert!(change.conflicts.is_empty());
+
+    authorized_keys.on_conflict = OnConflict::Refuse;
+    authorized_keys
+        .write_keys(keys(), &[], false)
+        .expect_err("Conflicting keys are refused");
+    file.assert(existing);
 }
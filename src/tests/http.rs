@@ -1,4 +1,5 @@
 use super::*;
+use crate::provenance;
 use proptest::prelude::*;
 
 /// Tests that we can get keys from a valid GitHub user
@@ -7,7 +8,7 @@ use proptest::prelude::*;
 fn test_get_github_budde25() {
     let n = Network::new();
     let url = get_github("budde25");
-    n.get_keys(&url)
+    n.get_keys(&url, None)
         .expect("Args are valid should return a result");
 }
 
@@ -17,7 +18,7 @@ fn test_get_github_budde25() {
 fn test_get_gitlab_budde25() {
     let n = Network::new();
     let url = get_gitlab("budde25", None);
-    n.get_keys(&url)
+    n.get_keys(&url, None)
         .expect("Args are valid should return a result");
 }
 
@@ -30,7 +31,7 @@ fn test_get_wisc_gitlab_budd() {
         "budde25",
         Some(Url::parse("https://gitlab.cs.wisc.edu/").unwrap()),
     );
-    n.get_keys(&url)
+    n.get_keys(&url, None)
         .expect("Args are valid should return a result");
 }
 
@@ -41,7 +42,7 @@ fn test_get_invalid_url() {
     let n = Network::new();
     let url =
         get_gitlab("budde25", Some(Url::parse("https://abc.edu/").unwrap()));
-    n.get_keys(&url)
+    n.get_keys(&url, None)
         .expect_err("Args not valid should not return result, 404");
 }
 
@@ -118,3 +119,138 @@ proptest! {
         Url::parse(&gitlab).expect("URL should be valid");
     }
 }
+
+/// Tests that the GitLab API url is made from the .keys url, keeping the path GitLab is hosted under
+#[test]
+fn test_gitlab_api_url() {
+    assert_eq!(
+        gitlab_api_url("https://gitlab.com/budde25.keys").unwrap(),
+        "https://gitlab.com/api/v4/users/budde25/keys?per_page=100"
+    );
+    assert_eq!(
+        gitlab_api_url("https://example.com/gitlab/budde25.keys").unwrap(),
+        "https://example.com/gitlab/api/v4/users/budde25/keys?per_page=100"
+    );
+    assert_eq!(gitlab_api_url("https://example.com/keys"), None);
+}
+
+/// Tests that only gitlab.com and the configured hosts are treated as GitLab
+#[test]
+fn test_gitlab_hosts() {
+    let network = Network::new();
+    let is_gitlab = |n: &Network, url: &str| {
+        provenance::is_gitlab_host(url, &n.gitlab_hosts)
+    };
+    assert!(is_gitlab(&network, "https://gitlab.com/budde25.keys"));
+    assert!(!is_gitlab(&network, "https://example.com/budde25.keys"));
+    assert_eq!(
+        network
+            .account_id("https://example.com/budde25.keys")
+            .unwrap(),
+        None
+    );
+    let network = network.with_gitlab_hosts(&[String::from("Example.com")]);
+    assert!(is_gitlab(&network, "https://example.com/budde25.keys"));
+}
+
+/// Tests that the next page of a GitLab response is found, and that the last page has none
+#[test]
+fn test_next_page() {
+    let mut headers = HeaderMap::new();
+    assert_eq!(next_page(&headers), None);
+    headers.insert("x-next-page", "".parse().unwrap());
+    assert_eq!(next_page(&headers), None);
+    headers.insert("x-next-page", "2".parse().unwrap());
+    assert_eq!(next_page(&headers).unwrap(), "2");
+}
+
+/// Tests that GitLab keys get their expiry, and signing only keys are skipped
+#[test]
+fn test_parse_gitlab_keys() {
+    let json = r#"[
+        {"id": 1, "title": "laptop", "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn bob@laptop", "created_at": "2022-06-01T14:25:01.000Z", "expires_at": "2022-06-30T00:00:00.000Z", "usage_type": "auth"},
+        {"id": 2, "title": "old", "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAICRDxyCYqJ/4RK8qJolhgsD5hb11ChbKpkHmB2rHUxGf", "expires_at": null},
+        {"id": 3, "title": "signing", "key": "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIC0g0z2T7eEskrJtl5VxuZcy69tdtKIAvTWmeloVRqEz", "expires_at": null, "usage_type": "signing"}
+    ]"#;
+    let keys = parse_gitlab_keys(json).unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(
+        keys[0].expires(),
+        Some(parse_gitlab_date("2022-06-30").unwrap())
+    );
+    assert_eq!(keys[0].comment.as_deref(), Some("bob@laptop"));
+    assert_eq!(
+        keys[0].created,
+        Some(parse_gitlab_date("2022-06-01T14:25:01Z").unwrap())
+    );
+    assert_eq!(keys[1].expires(), None);
+    assert_eq!(keys[1].created, None);
+
+    parse_gitlab_keys("{}").expect_err("Not a list of keys");
+}
+
+/// Tests that accounts are looked up for GitHub and GitLab only
+#[test]
+fn test_account_api_url() {
+    let hosts = [String::from("example.com")];
+    assert_eq!(
+        account_api_url("https://github.com/budde25.keys", &hosts).unwrap(),
+        "https://api.github.com/users/budde25"
+    );
+    assert_eq!(
+        account_api_url("https://example.com/gitlab/budde25.keys", &hosts)
+            .unwrap(),
+        "https://example.com/gitlab/api/v4/users?username=budde25"
+    );
+    assert_eq!(
+        account_api_url("https://example.com/gitlab/budde25.keys", &[]),
+        None
+    );
+    assert_eq!(
+        account_api_url("https://launchpad.net/~budde25/+sshkeys", &hosts),
+        None
+    );
+    assert_eq!(account_api_url("https://example.com/keys", &hosts), None);
+}
+
+/// Tests that the account ID is read from either API
+#[test]
+fn test_parse_account_id() {
+    assert_eq!(
+        parse_account_id(r#"{"login": "budde25", "id": 1234, "type": "User"}"#)
+            .unwrap(),
+        Some(String::from("1234"))
+    );
+    assert_eq!(
+        parse_account_id(
+            r#"[{"id": 42, "username": "budd", "state": "active"}]"#
+        )
+        .unwrap(),
+        Some(String::from("42"))
+    );
+    assert_eq!(parse_account_id("[]").unwrap(), None);
+    parse_account_id(r#"{"message": "Not Found"}"#).expect_err("No ID");
+}
+
+/// Tests that a missing account and rate limiting are told apart from other errors, also with context
+#[test]
+fn test_is_gone() {
+    let gone: anyhow::Error = AccountGone {
+        url: get_github("budde25"),
+    }
+    .into();
+    assert!(is_gone(&gone));
+    assert!(is_gone(
+        &gone.context("Unable to verify the pinned account")
+    ));
+    assert!(!is_gone(&anyhow!("500 Internal Server Error")));
+
+    let limited: anyhow::Error = RateLimited {
+        url: get_github("budde25"),
+    }
+    .into();
+    assert!(is_rate_limited(&limited.context("Unable to verify")));
+    assert!(!is_gone(&anyhow!(RateLimited {
+        url: get_github("budde25")
+    })));
+}
@@ -0,0 +1,45 @@
+use super::*;
+use assert_fs::prelude::*;
+
+/// Tests that every local user maps to the urls of its identities
+#[test]
+fn test_parse_mapping() {
+    let entries = parse(
+        "deploy = [\"gh:alice\", \"lp:asmith\"]\n\"%devops\" = [\"gl:bob\", \"url:https://example.com/keys\"]\n",
+    )
+    .unwrap();
+    assert_eq!(entries.len(), 2);
+    assert_eq!(entries[0].user, "%devops");
+    assert_eq!(
+        entries[0].identities(),
+        "gitlab:bob url:https://example.com/keys"
+    );
+    assert_eq!(
+        entries[0].urls().unwrap(),
+        vec!["https://gitlab.com/bob.keys", "https://example.com/keys"]
+    );
+    assert_eq!(entries[1].user, "deploy");
+    assert_eq!(
+        entries[1].urls().unwrap(),
+        vec![
+            "https://github.com/alice.keys",
+            "https://launchpad.net/~asmith/+sshkeys"
+        ]
+    );
+
+    assert!(parse("").unwrap().is_empty());
+    parse("deploy = [\"gh\"]").expect_err("Missing remote username");
+    parse("deploy = [\"bb:alice\"]").expect_err("Unknown provider");
+    parse("deploy = [\"url:example\"]").expect_err("Invalid url");
+    parse("deploy = \"gh:alice\"").expect_err("Not a list");
+}
+
+/// Tests that a missing mapping file is an error
+#[test]
+fn test_load_mapping() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let file = temp.child("users.toml");
+    load(file.path()).expect_err("Missing file");
+    file.write_str("ci = [\"github:carol\"]\n").unwrap();
+    assert_eq!(load(file.path()).unwrap()[0].identities(), "github:carol");
+}
@@ -0,0 +1,125 @@
+use super::*;
+use assert_fs::prelude::*;
+
+/// Sets up a home directory with an authorized_keys file, expected to be owned by the current user
+fn setup(temp: &assert_fs::TempDir) -> (PathBuf, Expected) {
+    let ssh = temp.child(".ssh");
+    ssh.create_dir_all().unwrap();
+    let file = ssh.child("authorized_keys");
+    file.touch().unwrap();
+    fs::set_permissions(ssh.path(), fs::Permissions::from_mode(0o700)).unwrap();
+    fs::set_permissions(file.path(), fs::Permissions::from_mode(0o600))
+        .unwrap();
+    fs::set_permissions(temp.path(), fs::Permissions::from_mode(0o755))
+        .unwrap();
+    let expected = Expected {
+        user: Uid::current(),
+        home: temp.path().to_path_buf(),
+        uid: Uid::current(),
+        gid: Gid::current(),
+        file_mode: 0o600,
+        dir_mode: Some(0o700),
+    };
+    (file.path().to_path_buf(), expected)
+}
+
+/// Tests that correct permissions have no problems
+#[test]
+fn test_audit_ok() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (file, expected) = setup(&temp);
+    assert_eq!(audit(&file, &expected).unwrap(), vec![]);
+}
+
+/// Tests that writable files and directories are refused, and other modes are reported and repaired
+#[test]
+fn test_audit_and_repair() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (file, expected) = setup(&temp);
+    fs::set_permissions(&file, fs::Permissions::from_mode(0o664)).unwrap();
+    fs::set_permissions(
+        file.parent().unwrap(),
+        fs::Permissions::from_mode(0o755),
+    )
+    .unwrap();
+    fs::set_permissions(temp.path(), fs::Permissions::from_mode(0o777))
+        .unwrap();
+
+    let problems = audit(&file, &expected).unwrap();
+    assert_eq!(problems.len(), 3);
+    assert!(problems[0].refused); // File is group writable
+    assert!(!problems[1].refused); // .ssh is readable by others
+    assert!(problems[2].refused); // Home is world writable
+    assert_eq!(problems[2].path, temp.path());
+
+    repair(&file, &expected).unwrap();
+    let problems = audit(&file, &expected).unwrap();
+    assert_eq!(problems.len(), 1, "The home directory is not repaired");
+    assert_eq!(fs::metadata(&file).unwrap().mode() & 0o7777, 0o600);
+}
+
+/// Tests that a missing file or a directory in its place is reported
+#[test]
+fn test_audit_not_file() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (file, expected) = setup(&temp);
+    fs::remove_file(&file).unwrap();
+    let problems = audit(&file, &expected).unwrap();
+    assert!(!problems[0].refused);
+    fs::create_dir(&file).unwrap();
+    let problems = audit(&file, &expected).unwrap();
+    assert!(problems[0].refused);
+}
+
+/// Tests that only a directory inside the home directory is managed
+#[test]
+fn test_expected_user() {
+    let user = User::from_name("root").unwrap().unwrap();
+    let expected =
+        Expected::user(&user, &user.dir.join(".ssh/authorized_keys"));
+    assert_eq!(expected.dir_mode, Some(0o700));
+    let expected = Expected::user(&user, &user.dir.join("authorized_keys"));
+    assert_eq!(expected.dir_mode, None);
+    let expected = Expected::user(&user, Path::new("/etc/ssh/keys/root"));
+    assert_eq!(expected.dir_mode, None);
+}
+
+/// Tests that symbolic links are found in the home directory, but not above it
+#[test]
+fn test_find_symlink() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (file, expected) = setup(&temp);
+    let home = Some(expected.home.as_path());
+    assert_eq!(find_symlink(&file, home), None);
+
+    let linked = temp.child("linked");
+    std::os::unix::fs::symlink(file.parent().unwrap(), linked.path()).unwrap();
+    let through = linked.path().join("authorized_keys");
+    assert_eq!(find_symlink(&through, home), Some(linked.path().to_owned()));
+    // Outside of the home directory only the file itself is checked
+    assert_eq!(find_symlink(&through, None), None);
+    assert_eq!(
+        find_symlink(linked.path(), None),
+        Some(linked.path().to_owned())
+    );
+}
+
+/// Tests that symbolic links are reported, and not repaired through
+#[test]
+fn test_audit_symlink() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let (file, expected) = setup(&temp);
+    let target = temp.child("target");
+    target.touch().unwrap();
+    fs::set_permissions(target.path(), fs::Permissions::from_mode(0o666))
+        .unwrap();
+    fs::remove_file(&file).unwrap();
+    std::os::unix::fs::symlink(target.path(), &file).unwrap();
+
+    let problems = audit(&file, &expected).unwrap();
+    assert_eq!(problems.len(), 1);
+    assert!(problems[0].message.contains("symbolic link"));
+    repair(&file, &expected).expect_err("Symbolic links are not repaired");
+    let mode = fs::metadata(target.path()).unwrap().mode() & 0o777;
+    assert_eq!(mode, 0o666);
+}
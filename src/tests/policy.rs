@@ -0,0 +1,57 @@
+use super::*;
+use crate::keys::parse;
+
+const KEYS: &str = r"ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABgQDQMhVpxA6LKHvlucLWDR2alNGMpHUmmFu85nqruHqq4+Hz5+2pgGIjUckJTiU6MK/AasF9gcdWS/XeNK6/TOkWopM3/ZU1arwLJiQ88Ah3TRoJCmdVmKkT99nraYHdttnwRvy1PrELRkH5Hbpga2KWhePjpvP9hO3yRlqbKoCTDlUu8zGOoP4x4eoym67o29uXeuD31zzEvnWxBXFSAppnCWY90fdBpm+pBnEhqbI9mPHQorAn23EXrCXlWT27DHJy1ry4BNFaZRFptF8A2e4TRF1DXdLKsWW54dcReqtAot1ZKP+tCroeGiXMojBbcji/LSGOhtjGlbin3fgTR6lA0YRN773SFrEg2Lkj09NgTEMH9BJztokSQeFnzyYJTeHYXcjavb/a1JY7m/FF30GlnjsyWT+J0p1sg/xl0U/8TNSp1qiUfEr8RVtcCCmZBYf5GLuySaODjRTwcT3OneIDdimLPY9thf2TkPBqtYCyllCO0/XcfLTCHiEGkKSZJRc= rsa3072
+ssh-rsa AAAAB3NzaC1yc2EAAAADAQABAAABAQDPx4jsUuivW/Yz0r7eD/InptzObq+qmwEP7fJrNZIOkKYyfVaxIxHYnAix7h4Qjk6dRq15to9slBSohRlXpXAx0WFpOMRgxC56uqnbGfh3fh8XxEIr23OHxiwoh4paS6CKu9Jz53S8lM6jSHsdH+0CmLm/iEw9Y0KtzOEzee6RR6EJUvs4TGSvaapOQJse4ZQNFJU0xBMVaGs4HQ2VitwrWVn/lvJoSoWk2fAAEGLcI2FOEoMBfnaAwyRj3F/L3hJ4vu77N7qvxdVCz7FRAEGPBcnoaeB4ivA2MXz3tEkHAilMTiUIMdPjS65lPyXfzWvlVQid3iMOb7oQcD4cI3oJ rsa2048
+ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn ed25519
+sk-ssh-ed25519@openssh.com AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIMEFcD8vlVs32BcDIVBmSA7csf8wAY2A2oVsBFhrB4bCAAAABHNzaDo= sk";
+
+/// Returns the comments of the keys, to identify them
+fn comments(keys: &[AuthorizedKey]) -> Vec<&str> {
+    keys.iter().map(|x| x.comment.as_deref().unwrap()).collect()
+}
+
+/// Tests that specs are parsed and formatted back the same
+#[test]
+fn test_parse_spec() {
+    let spec = "allow=ssh-ed25519,sk-* deny=ssh-dss min-rsa-bits=3072";
+    let policy: Policy = spec.parse().unwrap();
+    assert_eq!(policy.allow, vec!["ssh-ed25519", "sk-*"]);
+    assert_eq!(policy.deny, vec!["ssh-dss"]);
+    assert_eq!(policy.min_rsa_bits, 3072);
+    assert_eq!(policy.to_string(), spec);
+    assert_eq!("".parse::<Policy>().unwrap(), Policy::default());
+
+    "allow".parse::<Policy>().expect_err("Missing value");
+    "min-rsa-bits=lots"
+        .parse::<Policy>()
+        .expect_err("Not a number");
+    "max-bits=1".parse::<Policy>().expect_err("Unknown rule");
+}
+
+/// Tests the rules each reject the right keys, with a reason
+#[test]
+fn test_enforce() {
+    let keys = parse(KEYS);
+    let (accepted, rejected) = enforce(keys.clone(), &[&Policy::default()]);
+    assert_eq!(accepted.len(), 4);
+    assert!(rejected.is_empty());
+
+    let min_rsa: Policy = "min-rsa-bits=3072".parse().unwrap();
+    let (accepted, rejected) = enforce(keys.clone(), &[&min_rsa]);
+    assert_eq!(comments(&accepted), vec!["rsa3072", "ed25519", "sk"]);
+    assert_eq!(
+        rejected[0].reason,
+        "RSA key has 2048 bits, at least 3072 are required"
+    );
+
+    let allow: Policy = "allow=ssh-ed25519,sk-*".parse().unwrap();
+    let (accepted, _) = enforce(keys.clone(), &[&allow]);
+    assert_eq!(comments(&accepted), vec!["ed25519", "sk"]);
+
+    let deny: Policy = "deny=sk-*".parse().unwrap();
+    let (accepted, rejected) = enforce(keys, &[&min_rsa, &deny]);
+    assert_eq!(comments(&accepted), vec!["rsa3072", "ed25519"]);
+    assert_eq!(rejected.len(), 2);
+    assert!(rejected[1].to_string().contains("is denied"));
+}
@@ -0,0 +1,122 @@
+use super::*;
+
+const KEY: &str = "ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJ2VpcsTaFwEGD1uiBwRs1L+5AcPRaGjQd1axOQDLTyn";
+
+/// Tests that the provider and username are worked out from the provider urls
+#[test]
+fn test_from_url() {
+    let hosts = [String::from("gitlab.com"), String::from("git.example.com")];
+    let github = from_url("https://github.com/bob.keys", &hosts);
+    assert_eq!(
+        (github.provider.as_str(), github.user.as_str()),
+        ("github", "bob")
+    );
+    assert_eq!(github.host, None);
+
+    let launchpad = from_url("https://launchpad.net/~bob/+sshkeys", &hosts);
+    assert_eq!(
+        (launchpad.provider.as_str(), launchpad.user.as_str()),
+        ("launchpad", "bob")
+    );
+
+    let gitlab = from_url("https://gitlab.com/bob.keys", &hosts);
+    assert_eq!(
+        (gitlab.provider.as_str(), gitlab.user.as_str()),
+        ("gitlab", "bob")
+    );
+    assert_eq!(gitlab.host, None);
+    let gitlab = from_url("https://git.example.com/bob.keys", &hosts);
+    assert_eq!(gitlab.host, Some(String::from("git.example.com")));
+
+    let unknown = from_url("https://keys.example.org/bob.keys", &hosts);
+    assert_eq!(
+        (unknown.provider.as_str(), unknown.user.as_str()),
+        ("url", "https://keys.example.org/bob.keys")
+    );
+    assert_eq!(unknown.host, None);
+
+    let other = from_url("https://example.com/keys", &hosts);
+    assert_eq!(
+        (other.provider.as_str(), other.user.as_str()),
+        ("url", "https://example.com/keys")
+    );
+}
+
+/// Tests that tagged keys keep their original comment and can be attributed again
+#[test]
+fn test_tag_and_parse() {
+    let key: AuthorizedKey = format!("{} bob@my laptop", KEY).parse().unwrap();
+    let provenance =
+        Provenance::new("https://github.com/bob.keys", Some(3), &[]);
+    let tagged = provenance.tag(vec![key]);
+    let comment = tagged[0].comment.as_ref().unwrap();
+    assert!(
+        comment.starts_with("keysync provider=github user=bob job=3 imported=")
+    );
+    assert!(comment.ends_with(" comment=bob@my laptop"));
+
+    let line = tagged[0].to_string();
+    let parsed: AuthorizedKey = line.parse().unwrap();
+    let back = Provenance::from_comment(parsed.comment.as_ref().unwrap())
+        .expect("Should parse back");
+    assert_eq!(back.provider, "github");
+    assert_eq!(back.user, "bob");
+    assert_eq!(back.job, Some(3));
+    assert_eq!(back.imported, provenance.imported);
+    assert_eq!(back.comment, Some(String::from("bob@my laptop")));
+    assert!(is_managed(&parsed));
+    assert!(is_from_job(&parsed, 3));
+    assert!(!is_from_job(&parsed, 4));
+    assert!(!is_mapped(&parsed));
+}
+
+/// Tests that keys apply imports are marked as mapped, keeping the rest of their provenance
+#[test]
+fn test_mark_mapped() {
+    let key: AuthorizedKey = format!("{} bob@laptop", KEY).parse().unwrap();
+    let tagged = Provenance::new("https://github.com/bob.keys", None, &[])
+        .tag(vec![key]);
+    let mapped = mark_mapped(tagged);
+    let comment = mapped[0].comment.as_ref().unwrap();
+    assert!(comment.starts_with("keysync provider=github user=bob mapped=true"));
+    assert!(comment.ends_with(" comment=bob@laptop"));
+    assert!(is_mapped(&mapped[0]));
+
+    let own: AuthorizedKey = format!("{} laptop", KEY).parse().unwrap();
+    assert_eq!(mark_mapped(vec![own.clone()]), [own]);
+}
+
+/// Tests that legacy keys are managed without details, and other comments are not managed
+#[test]
+fn test_managed() {
+    let legacy: AuthorizedKey =
+        format!("{} #ssh-import keysync", KEY).parse().unwrap();
+    assert!(is_managed(&legacy));
+    assert_eq!(
+        Provenance::from_comment(legacy.comment.as_ref().unwrap()),
+        None
+    );
+
+    let own: AuthorizedKey = format!("{} keysync laptop", KEY).parse().unwrap();
+    assert!(!is_managed(&own));
+    let none: AuthorizedKey = KEY.parse().unwrap();
+    assert!(!is_managed(&none));
+}
+
+/// Tests that sources are parsed with abbreviations and match the provider and username
+#[test]
+fn test_source() {
+    let key: AuthorizedKey = format!("{} bob@laptop", KEY).parse().unwrap();
+    let tagged = Provenance::new("https://github.com/bob.keys", None, &[])
+        .tag(vec![key]);
+
+    let source: Source = "gh:bob".parse().unwrap();
+    assert_eq!(source, "github:bob".parse().unwrap());
+    assert!(source.matches(&tagged[0]));
+    assert!("gh".parse::<Source>().unwrap().matches(&tagged[0]));
+    assert!(!"gh:alice".parse::<Source>().unwrap().matches(&tagged[0]));
+    assert!(!"lp:bob".parse::<Source>().unwrap().matches(&tagged[0]));
+    "bitbucket:bob"
+        .parse::<Source>()
+        .expect_err("Unknown provider");
+}
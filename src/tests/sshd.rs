@@ -0,0 +1,158 @@
+use super::*;
+use assert_fs::prelude::*;
+
+/// A passwd entry to expand tokens with, root always exists
+fn root() -> User {
+    User::from_name("root").unwrap().unwrap()
+}
+
+/// Tests that a missing or empty config uses sshd's defaults
+#[test]
+fn test_default_files() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let config = SshdConfig::load(temp.path().join("sshd_config")).unwrap();
+    assert_eq!(
+        config.authorized_keys_files(&root()).unwrap(),
+        vec![
+            PathBuf::from("/root/.ssh/authorized_keys"),
+            PathBuf::from("/root/.ssh/authorized_keys2")
+        ]
+    );
+}
+
+/// Tests that tokens are expanded and relative paths are resolved against the home directory
+#[test]
+fn test_tokens() {
+    let mut config = SshdConfig::default();
+    config
+        .parse(
+            "# A comment\nAuthorizedKeysFile /etc/keys/%u.%U .ssh/%%keys \"%h/with space\" # trailing comment\n",
+            0,
+        )
+        .unwrap();
+    assert_eq!(
+        config.authorized_keys_files(&root()).unwrap(),
+        vec![
+            PathBuf::from("/etc/keys/root.0"),
+            PathBuf::from("/root/.ssh/%keys"),
+            PathBuf::from("/root/with space"),
+        ]
+    );
+
+    let mut config = SshdConfig::default();
+    config.parse("AuthorizedKeysFile %x\n", 0).unwrap();
+    config
+        .authorized_keys_files(&root())
+        .expect_err("Unknown token");
+}
+
+/// Tests that the first value is used, keywords are case insensitive and Match blocks are ignored
+#[test]
+fn test_first_value_and_match() {
+    let mut config = SshdConfig::default();
+    config
+        .parse(
+            "Match User deploy\n    AuthorizedKeysFile /srv/deploy/keys\n",
+            0,
+        )
+        .unwrap();
+    assert_eq!(config, SshdConfig::default());
+
+    let mut config = SshdConfig::default();
+    config
+        .parse(
+            "authorizedkeysfile=/first\nAuthorizedKeysFile /second\nMatch all\nAuthorizedKeysFile /third\n",
+            0,
+        )
+        .unwrap();
+    assert_eq!(
+        config.authorized_keys_files(&root()).unwrap(),
+        vec![PathBuf::from("/first")]
+    );
+}
+
+/// Tests that none disables authorized keys files
+#[test]
+fn test_none() {
+    let mut config = SshdConfig::default();
+    config.parse("AuthorizedKeysFile none\n", 0).unwrap();
+    assert!(config.authorized_keys_files(&root()).unwrap().is_empty());
+}
+
+/// Tests that the CA keys file is read, and none or a missing setting means there is none
+#[test]
+fn test_trusted_user_ca_keys() {
+    let mut config = SshdConfig::default();
+    config
+        .parse(
+            "TrustedUserCAKeys /etc/ssh/ca.pub\nTrustedUserCAKeys /second\n",
+            0,
+        )
+        .unwrap();
+    assert_eq!(
+        config.trusted_user_ca_keys(),
+        Some(PathBuf::from("/etc/ssh/ca.pub"))
+    );
+
+    let mut config = SshdConfig::default();
+    config.parse("TrustedUserCAKeys none\n", 0).unwrap();
+    assert_eq!(config.trusted_user_ca_keys(), None);
+    assert_eq!(SshdConfig::default().trusted_user_ca_keys(), None);
+}
+
+/// Tests that included files are read in order, with wildcards
+#[test]
+fn test_include() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let conf_d = temp.child("sshd_config.d");
+    conf_d.create_dir_all().unwrap();
+    conf_d
+        .child("20-other.conf")
+        .write_str("AuthorizedKeysFile /second\n")
+        .unwrap();
+    conf_d
+        .child("10-keys.conf")
+        .write_str("Match User bob\nAuthorizedKeysFile /bob\n")
+        .unwrap();
+    conf_d
+        .child("15-keys.conf")
+        .write_str("AuthorizedKeysFile /var/lib/%u/keys\n")
+        .unwrap();
+    conf_d
+        .child("ignored.txt")
+        .write_str("AuthorizedKeysFile /no\n")
+        .unwrap();
+    let main = temp.child("sshd_config");
+    main.write_str(&format!(
+        "Include {}/*.conf\nAuthorizedKeysFile /last\n",
+        conf_d.path().display()
+    ))
+    .unwrap();
+
+    let config = SshdConfig::load(main.path()).unwrap();
+    assert_eq!(
+        config.authorized_keys_files(&root()).unwrap(),
+        vec![PathBuf::from("/var/lib/root/keys")]
+    );
+}
+
+/// Tests that recursive includes do not loop forever
+#[test]
+fn test_include_loop() {
+    let temp = assert_fs::TempDir::new().unwrap();
+    let main = temp.child("sshd_config");
+    main.write_str(&format!("Include {}\n", main.path().display()))
+        .unwrap();
+    SshdConfig::load(main.path()).expect_err("Include loop");
+}
+
+/// Tests the Include wildcards
+#[test]
+fn test_wildcard_match() {
+    assert!(wildcard_match("*.conf", "10-keys.conf"));
+    assert!(wildcard_match("*", ""));
+    assert!(wildcard_match("a?c*", "abcdef"));
+    assert!(wildcard_match("*keys*conf", "10-keys.conf"));
+    assert!(!wildcard_match("*.conf", "10-keys.conf.bak"));
+    assert!(!wildcard_match("a?c", "ac"));
+}
@@ -0,0 +1,82 @@
+use super::*;
+
+const RSA: &str = "AAAAB3NzaC1yc2EAAAADAQABAAABgQDQMhVpxA6LKHvlucLWDR2alNGMpHUmmFu85nqruHqq4+Hz5+2pgGIjUckJTiU6MK/AasF9gcdWS/XeNK6/TOkWopM3/ZU1arwLJiQ88Ah3TRoJCmdVmKkT99nraYHdttnwRvy1PrELRkH5Hbpga2KWhePjpvP9hO3yRlqbKoCTDlUu8zGOoP4x4eoym67o29uXeuD31zzEvnWxBXFSAppnCWY90fdBpm+pBnEhqbI9mPHQorAn23EXrCXlWT27DHJy1ry4BNFaZRFptF8A2e4TRF1DXdLKsWW54dcReqtAot1ZKP+tCroeGiXMojBbcji/LSGOhtjGlbin3fgTR6lA0YRN773SFrEg2Lkj09NgTEMH9BJztokSQeFnzyYJTeHYXcjavb/a1JY7m/FF30GlnjsyWT+J0p1sg/xl0U/8TNSp1qiUfEr8RVtcCCmZBYf5GLuySaODjRTwcT3OneIDdimLPY9thf2TkPBqtYCyllCO0/XcfLTCHiEGkKSZJRc=";
+const ECDSA: &str = "AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzODQAAABhBOqholMrsnxgYPRijyJqC9Lw530F27nIkkDh4nywuj2AbcWfpeuwO0IDGwX7Ph+wWbm2EIRkePfhcUHwXJbNRAerxKP/uUPD9kl7CiQKPcVzUezGh9DVA6Jfkpjb7/+mJw==";
+const ED25519: &str =
+    "AAAAC3NzaC1lZDI1NTE5AAAAIC0g0z2T7eEskrJtl5VxuZcy69tdtKIAvTWmeloVRqEz";
+const SK_ED25519: &str = "AAAAGnNrLXNzaC1lZDI1NTE5QG9wZW5zc2guY29tAAAAIMEFcD8vlVs32BcDIVBmSA7csf8wAY2A2oVsBFhrB4bCAAAABHNzaDo=";
+const ED25519_CERT: &str = "AAAAIHNzaC1lZDI1NTE5LWNlcnQtdjAxQG9wZW5zc2guY29tAAAAIIvc1c/4RJKmoljiTRyVNrnyXzE6fUqE+jGOBSHRsGHcAAAAIEuZdKp5CErHg0CatQfAEGbyVvo0DFiXVYWmNoV8naVFAAAAAAAAAAAAAAABAAAAA2JvYgAAAAcAAAADYm9iAAAAAAAAAAD//////////wAAAAAAAACCAAAAFXBlcm1pdC1YMTEtZm9yd2FyZGluZwAAAAAAAAAXcGVybWl0LWFnZW50LWZvcndhcmRpbmcAAAAAAAAAFnBlcm1pdC1wb3J0LWZvcndhcmRpbmcAAAAAAAAACnBlcm1pdC1wdHkAAAAAAAAADnBlcm1pdC11c2VyLXJjAAAAAAAAAAAAAAAzAAAAC3NzaC1lZDI1NTE5AAAAIC0g0z2T7eEskrJtl5VxuZcy69tdtKIAvTWmeloVRqEzAAAAUwAAAAtzc2gtZWQyNTUxOQAAAED8C1+45MxYj+gZ+iEcjw9RqgtzAVKMxKz7ynPrA9tH8Aa0hmIH5exYIztoVBDSSWJKXM7hGrt/hcACKs2Wuu4E";
+
+/// Tests that the sizes and fingerprints match ssh-keygen -l
+#[test]
+fn test_decode() {
+    let cases = [
+        (
+            "ssh-rsa",
+            RSA,
+            3072,
+            "SHA256:ZtD4guLAAqFDD/ebiOFix78+osOFSztDfibVmTUnQg8",
+        ),
+        (
+            "ecdsa-sha2-nistp384",
+            ECDSA,
+            384,
+            "SHA256:SAN/slp1dX9ccYZiZlL3JIGbxtL1QESSkWqgk5AEiqA",
+        ),
+        (
+            "sk-ssh-ed25519@openssh.com",
+            SK_ED25519,
+            256,
+            "SHA256:LUVUwglj5WqK2s3ncWlc8g/e+yyL2sUCLjEHevB2xL8",
+        ),
+    ];
+    for (key_type, blob, bits, fingerprint) in cases {
+        let data = decode(key_type, blob).unwrap();
+        assert_eq!(data.bits, bits, "{}", key_type);
+        assert_eq!(data.fingerprint, fingerprint, "{}", key_type);
+    }
+}
+
+/// Tests that a certificate has the fingerprint of the key it certifies
+#[test]
+fn test_decode_certificate() {
+    let cert =
+        decode("ssh-ed25519-cert-v01@openssh.com", ED25519_CERT).unwrap();
+    assert_eq!(
+        cert.fingerprint,
+        "SHA256:pyTi0DE6EmOgtR/6RvjEhUbc0BVZ6ZkBaLh3OsVBpBU"
+    );
+    assert_eq!(cert.bits, 256);
+}
+
+/// Tests that mismatched types, truncated and malformed blobs are rejected
+#[test]
+fn test_decode_invalid() {
+    decode("ssh-rsa", ED25519).expect_err("Embedded type differs");
+    decode("ssh-ed25519", "not base64!").expect_err("Not base64");
+    decode("ssh-ed25519", &ED25519[..ED25519.len() - 8])
+        .expect_err("Truncated");
+
+    let mut extra = base64::decode(ED25519).unwrap();
+    extra.push(0);
+    decode("ssh-ed25519", &base64::encode(extra)).expect_err("Trailing bytes");
+
+    let mut bytes = encode_string(b"ecdsa-sha2-nistp256");
+    bytes.extend(encode_string(b"nistp256"));
+    bytes.extend(encode_string(&[0x04; 33]));
+    decode("ecdsa-sha2-nistp256", &base64::encode(bytes))
+        .expect_err("Point is too short for the curve");
+
+    let mut bytes = encode_string(b"ssh-rsa");
+    bytes.extend(encode_string(&[0x01, 0x00, 0x01]));
+    bytes.extend(encode_string(&[0x00, 0xc0, 0x00]));
+    decode("ssh-rsa", &base64::encode(bytes)).expect_err("Even modulus");
+}
+
+/// Tests the bit length of big endian integers with leading zeros
+#[test]
+fn test_bit_length() {
+    assert_eq!(bit_length(&[]), 0);
+    assert_eq!(bit_length(&[0x00, 0x80, 0x00]), 16);
+    assert_eq!(bit_length(&[0x01, 0x00]), 9);
+}
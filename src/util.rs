@@ -1,52 +1,76 @@
 use anyhow::{anyhow, Result};
-use nix::unistd::Uid;
-use regex::Regex;
+use chrono::{Duration, Local, NaiveDate, NaiveDateTime, TimeZone};
+use log::warn;
+use nix::unistd::{Gid, Group, Uid, User};
 use rustyline::{error::ReadlineError, Editor};
+use std::fs;
 use std::process::{exit, Command};
 
-// From regex example
-macro_rules! regex {
-    ($re:literal $(,)?) => {{
-        static RE: once_cell::sync::OnceCell<regex::Regex> =
-            once_cell::sync::OnceCell::new();
-        RE.get_or_init(|| regex::Regex::new($re).unwrap())
-    }};
-}
+use super::keys::AuthorizedKey;
 
-/// Filters the keys to prevent adding duplicates, also adds import comment
+/// Filters the keys to prevent adding duplicates
 /// Returns a list of keys to that are unique
-pub fn filter_keys(to_add: Vec<String>, exist: Vec<String>) -> Vec<String> {
+pub fn filter_keys(
+    to_add: Vec<AuthorizedKey>,
+    exist: Vec<AuthorizedKey>,
+) -> Vec<AuthorizedKey> {
     to_add
-        .iter()
-        .filter(|x| !exist.contains(x))
-        .map(|x| x.to_owned() + " #ssh-import keysync")
+        .into_iter()
+        .filter(|x| !exist.iter().any(|y| y.same_key(x)))
         .collect()
 }
 
-/// Splits a string of keys into a list of keys based off a newline, also discards invalid keys
-pub fn split_keys(all_keys: &str) -> Vec<String> {
-    all_keys
-        .split('\n')
-        .map(|x| x.trim())
-        .filter(|x| {
-            let re: &Regex = regex!(
-                r"^(ssh-rsa AAAAB3NzaC1yc2|ecdsa-sha2-nistp256 AAAAE2VjZHNhLXNoYTItbmlzdHAyNT|ecdsa-sha2-nistp384 AAAAE2VjZHNhLXNoYTItbmlzdHAzODQAAAAIbmlzdHAzOD|ecdsa-sha2-nistp521 AAAAE2VjZHNhLXNoYTItbmlzdHA1MjEAAAAIbmlzdHA1Mj|ssh-ed25519 AAAAC3NzaC1lZDI1NTE5|ssh-dss AAAAB3NzaC1kc3)",
-            );
-            re.is_match(x)
-        })
-        .map(|x| x.to_owned())
-        .collect()
+/// The passwd file, read for the users whose primary group is a group
+const PASSWD_PATH: &str = "/etc/passwd";
+
+/// The longest duration accepted, about 100 years, so the expiry always fits in a date
+const MAX_DURATION_MINUTES: i64 = 100 * 366 * 24 * 60;
+
+/// Parses a duration of a number and a unit Ex: 30m, 8h, 2d or 1w
+pub fn parse_duration(value: &str) -> Result<Duration> {
+    let invalid = || {
+        anyhow!(
+            "Invalid duration, use a number and m, h, d or w Ex: 8h: {}",
+            value
+        )
+    };
+    let unit = value.chars().last().ok_or_else(invalid)?;
+    let amount: i64 = value[..value.len() - unit.len_utf8()]
+        .parse()
+        .map_err(|_| invalid())?;
+    if amount <= 0 {
+        return Err(invalid());
+    }
+    let minutes_per_unit: i64 = match unit {
+        'm' => 1,
+        'h' => 60,
+        'd' => 60 * 24,
+        'w' => 60 * 24 * 7,
+        _ => return Err(invalid()),
+    };
+    match amount.checked_mul(minutes_per_unit) {
+        Some(minutes) if minutes <= MAX_DURATION_MINUTES => {
+            Ok(Duration::minutes(minutes))
+        }
+        _ => Err(anyhow!("Duration is longer than 100 years: {}", value)),
+    }
 }
 
-/// Removes any garbage from the keys Ex: comments
-pub fn clean_keys(original_keys: Vec<String>) -> Vec<String> {
-    original_keys
-        .iter()
-        .map(|x| {
-            x.split(' ').map(|x| x.to_owned()).collect::<Vec<String>>()[0..2]
-                .join(" ")
-        })
-        .collect()
+/// Parses a local date, with an optional time Ex: 2022-06-30 or 2022-06-30T18:00, a date alone is midnight at its start
+pub fn parse_date(value: &str) -> Result<chrono::DateTime<Local>> {
+    let time = match NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        Ok(d) => d.and_hms_opt(0, 0, 0).unwrap(),
+        Err(_) => ["%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M"]
+            .iter()
+            .find_map(|f| NaiveDateTime::parse_from_str(value, f).ok())
+            .ok_or_else(|| {
+                anyhow!("Invalid date, use YYYY-MM-DD[THH:MM]: {}", value)
+            })?,
+    };
+    Local
+        .from_local_datetime(&time)
+        .earliest()
+        .ok_or_else(|| anyhow!("Date does not exist locally: {}", value))
 }
 
 /// Runs the current command line options as root, (assuming sudo is installed)
@@ -106,6 +130,60 @@ pub fn get_current_user() -> Result<String> {
     Ok(std::env::var("USER")?)
 }
 
+/// Gets the members of a group, listed in the group database or with it as their primary group, root and unknown users are skipped
+/// Primary members are found in the passwd file, so users only known through other sources need to be listed in the group
+pub fn group_members(name: &str) -> Result<Vec<String>> {
+    let group = Group::from_name(name)?
+        .ok_or_else(|| anyhow!("group '{}' does not exist on system", name))?;
+    let mut members: Vec<String> = group.mem;
+    match fs::read_to_string(PASSWD_PATH) {
+        Ok(passwd) => {
+            for user in primary_members(&passwd, group.gid) {
+                if !members.contains(&user) {
+                    members.push(user);
+                }
+            }
+        }
+        Err(e) => warn!(
+            "Unable to read {}, only listed members of group {} are used: {}",
+            PASSWD_PATH, name, e
+        ),
+    }
+    Ok(members
+        .into_iter()
+        .filter(|x| match User::from_name(x) {
+            Ok(Some(u)) if u.uid.is_root() => {
+                warn!("Skipping root, a member of group {}", name);
+                false
+            }
+            Ok(Some(_)) => true,
+            _ => {
+                warn!("Skipping unknown user {} of group {}", x, name);
+                false
+            }
+        })
+        .collect())
+}
+
+/// Gets the users of a passwd file whose primary group is the gid
+fn primary_members(passwd: &str, gid: Gid) -> Vec<String> {
+    passwd
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split(':').collect();
+            match fields.as_slice() {
+                [name, _, _, g, ..]
+                    if !name.is_empty()
+                        && g.parse::<u32>() == Ok(gid.as_raw()) =>
+                {
+                    Some(name.to_string())
+                }
+                _ => None,
+            }
+        })
+        .collect()
+}
+
 /// Unit Tests
 #[cfg(test)]
 #[path = "./tests/util.rs"]